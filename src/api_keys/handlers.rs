@@ -0,0 +1,60 @@
+use warp::Reply;
+use serde_json::json;
+use warp::reject;
+use chrono::Utc;
+
+use crate::{roles, users, WebResult};
+use crate::api_keys::models::{ApiKeyCreateRequest, ApiKeyCreateResponse, ApiKeyUpdateRequest};
+use crate::api_keys::service;
+use crate::api_keys::utils::validate_scopes;
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
+use crate::error::AppError;
+
+// Returns the API keys of the current user
+pub async fn get_api_keys_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_api_keys(&_user.id, _env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+pub async fn get_api_key_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_api_key(&_id, &_user.id, _env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+// Creates a key for the current user. The raw key is part of this response only.
+pub async fn create_api_key_handler(_req: ApiKeyCreateRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    if _req.name.trim().is_empty() {
+        return Err(reject::custom(AppError::InvalidApiKeyRequestError));
+    }
+    if matches!(_req.expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+        return Err(reject::custom(AppError::InvalidApiKeyRequestError));
+    }
+    let scopes = validate_scopes(&_req.scopes, &owner_permissions(&_env, &_user).await?).map_err(reject::custom)?;
+    let (api_key, key) = service::create_api_key(&_user.id, _req.name.trim(), scopes, _req.expires_at, _env.db()).await.map_err(reject::custom)?;
+    println!("[create_api_key_handler] User {} created API key {}", _user, &api_key.id);
+    Ok(warp::reply::json(&ApiKeyCreateResponse { api_key, key }))
+}
+
+pub async fn update_api_key_handler(_id: String, _req: ApiKeyUpdateRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let scopes = match &_req.scopes {
+        Some(scopes) => Some(validate_scopes(scopes, &owner_permissions(&_env, &_user).await?).map_err(reject::custom)?),
+        None => None,
+    };
+    let name = _req.name.map(|name| name.trim().to_owned()).filter(|name| !name.is_empty());
+    let result = service::update_api_key(&_id, &_user.id, name, scopes, _env.db()).await.map_err(reject::custom)?;
+    println!("[update_api_key_handler] User {} updated API key {}", _user, &_id);
+    Ok(warp::reply::json(&result))
+}
+
+pub async fn delete_api_key_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    service::delete_api_key(&_id, &_user.id, _env.db()).await.map_err(reject::custom)?;
+    println!("[delete_api_key_handler] User {} deleted API key {}", _user, &_id);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"API key deleted"})))
+}
+
+// Permissions of the role stored on the user rather than the one in the token, which may predate a demotion
+async fn owner_permissions(_env: &Environment, _user: &AuthUser) -> WebResult<Vec<String>> {
+    let owner = users::service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+    roles::service::permissions_for(_env, &owner.role.unwrap_or(Role::User)).await.map_err(reject::custom)
+}
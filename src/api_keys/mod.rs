@@ -0,0 +1,13 @@
+pub mod handlers;
+pub mod models;
+pub mod routes;
+pub mod service;
+pub mod utils;
+
+// Prepended to generated keys so they are easy to recognise, e.g. by secret scanners
+pub const API_KEY_PREFIX: &str = "rcn_";
+// Number of characters of the key kept in clear to tell keys apart
+pub const API_KEY_DISPLAY_LENGTH: usize = 12;
+
+pub const SCOPE_USER: &str = "user";
+pub const SCOPE_ADMIN: &str = "admin";
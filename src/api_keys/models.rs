@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Personal API key. Only the hash of the key is stored, `prefix` is kept so the owner can tell keys apart.
+#[derive(Clone, Serialize, Debug)]
+pub struct ApiKey {
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub user_id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiKeyCreateRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiKeyUpdateRequest {
+    pub name: Option<String>,
+    pub scopes: Option<Vec<String>>,
+}
+
+// The key itself is only returned once, when it is created
+#[derive(Serialize)]
+pub struct ApiKeyCreateResponse {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
@@ -0,0 +1,43 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::api_keys::handlers;
+use crate::environment::Environment;
+
+// API keys are managed from an interactive session only, so a leaked key cannot be used to mint new ones
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    let get_api_keys_route = warp::get().and(warp::path!("api" / "users" / "me" / "api-keys")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::get_api_keys_handler));
+
+    let get_api_key_route = warp::get().and(warp::path!("api" / "users" / "me" / "api-keys" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::get_api_key_handler));
+
+    let create_api_key_route = warp::post().and(warp::path!("api" / "users" / "me" / "api-keys")
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::create_api_key_handler));
+
+    let update_api_key_route = warp::put().and(warp::path!("api" / "users" / "me" / "api-keys" / String)
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::update_api_key_handler));
+
+    let delete_api_key_route = warp::delete().and(warp::path!("api" / "users" / "me" / "api-keys" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::delete_api_key_handler));
+
+    let routes = get_api_keys_route.or(get_api_key_route)
+        .or(create_api_key_route)
+        .or(update_api_key_route)
+        .or(delete_api_key_route);
+
+    routes.boxed()
+}
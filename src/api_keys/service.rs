@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::Database;
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use tokio::stream::StreamExt;
+
+use crate::Result;
+use crate::api_keys::{API_KEY_DISPLAY_LENGTH, API_KEY_PREFIX};
+use crate::api_keys::models::ApiKey;
+use crate::api_keys::utils::doc_to_api_key;
+use crate::auth::utils::{generate_token, hash_token};
+use crate::error::AppError;
+
+
+pub async fn create_indexes(_db: Database) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "api_keys",
+        "indexes": [
+            { "key": { "key_hash": 1 }, "name": "key_hash", "unique": true },
+            { "key": { "user_id": 1 }, "name": "user_id" },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [api_keys::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Stores a new key and returns it along with its raw value, which is never stored
+pub async fn create_api_key(user_id: &str, name: &str, scopes: Vec<String>, expires_at: Option<DateTime<Utc>>, _db: Database) -> Result<(ApiKey, String)> {
+    let key = format!("{}{}", API_KEY_PREFIX, generate_token());
+    let mut doc = doc! {
+        "key_hash": hash_token(&key),
+        "user_id": user_id,
+        "name": name,
+        "prefix": &key[..API_KEY_DISPLAY_LENGTH],
+        "scopes": scopes,
+        "created_at": Utc::now(),
+    };
+    if let Some(expires_at) = expires_at {
+        doc.insert("expires_at", expires_at);
+    }
+    let _result = _db.collection("api_keys").insert_one(doc.clone(), None).await.map_err(|_e| {
+        println!("ERROR [create_api_key] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    doc.insert("_id", _result.inserted_id);
+    Ok((doc_to_api_key(&doc)?, key))
+}
+
+
+pub async fn get_api_keys(user_id: &str, _db: Database) -> Result<Vec<ApiKey>> {
+    let filter = doc! { "user_id": user_id };
+    let mut _cursor = _db.collection("api_keys").find(filter, None).await.map_err(|_e| {
+        println!("ERROR [get_api_keys] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<ApiKey> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        result.push(doc_to_api_key(&doc?)?);
+    }
+    Ok(result)
+}
+
+
+// Keys are always looked up together with their owner, so users cannot reach each other's keys
+pub async fn get_api_key(id: &str, user_id: &str, _db: Database) -> Result<ApiKey> {
+    let filter = key_filter(id, user_id)?;
+    let found = _db.collection("api_keys").find_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [get_api_key] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    match found {
+        Some(doc) => doc_to_api_key(&doc),
+        None => Err(AppError::ApiKeyNotFoundError),
+    }
+}
+
+
+pub async fn update_api_key(id: &str, user_id: &str, name: Option<String>, scopes: Option<Vec<String>>, _db: Database) -> Result<ApiKey> {
+    let mut updates = Document::new();
+    if let Some(name) = name {
+        updates.insert("name", name);
+    }
+    if let Some(scopes) = scopes {
+        updates.insert("scopes", scopes);
+    }
+    if updates.is_empty() {
+        return get_api_key(id, user_id, _db).await;
+    }
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let updated = _db.collection("api_keys").find_one_and_update(key_filter(id, user_id)?, doc! { "$set": updates }, options).await.map_err(|_e| {
+        println!("ERROR [update_api_key] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    match updated {
+        Some(doc) => doc_to_api_key(&doc),
+        None => Err(AppError::ApiKeyNotFoundError),
+    }
+}
+
+
+pub async fn delete_api_key(id: &str, user_id: &str, _db: Database) -> Result<()> {
+    let _result = _db.collection("api_keys").delete_one(key_filter(id, user_id)?, None).await.map_err(|_e| {
+        println!("ERROR [delete_api_key] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.deleted_count == 0 {
+        return Err(AppError::ApiKeyNotFoundError);
+    }
+    Ok(())
+}
+
+
+pub async fn delete_user_api_keys(user_id: &str, _db: Database) -> Result<()> {
+    _db.collection("api_keys").delete_many(doc! { "user_id": user_id }, None).await.map_err(|_e| {
+        println!("ERROR [delete_user_api_keys] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Looks up an unexpired key by its raw value and records that it was used
+pub async fn use_api_key(key: &str, _db: Database) -> Result<Option<ApiKey>> {
+    let now = Utc::now();
+    let filter = doc! {
+        "key_hash": hash_token(key),
+        "$or": [ { "expires_at": null }, { "expires_at": { "$gt": now } } ],
+    };
+    let updates = doc! { "$set": { "last_used_at": now } };
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let updated = _db.collection("api_keys").find_one_and_update(filter, updates, options).await.map_err(|_e| {
+        println!("ERROR [use_api_key] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    updated.as_ref().map(doc_to_api_key).transpose()
+}
+
+
+fn key_filter(id: &str, user_id: &str) -> Result<Document> {
+    let oid = ObjectId::with_string(id).map_err(|_| AppError::ApiKeyNotFoundError)?;
+    Ok(doc! { "_id": oid, "user_id": user_id })
+}
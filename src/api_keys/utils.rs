@@ -0,0 +1,108 @@
+use mongodb::bson::Document;
+
+use crate::Result;
+use crate::api_keys::{SCOPE_ADMIN, SCOPE_USER};
+use crate::api_keys::models::ApiKey;
+use crate::error::AppError;
+
+
+pub fn doc_to_api_key(doc: &Document) -> Result<ApiKey> {
+    let scopes = doc.get_array("scopes")?.iter()
+        .filter_map(|scope| scope.as_str().map(str::to_owned))
+        .collect();
+    let result = ApiKey {
+        id: doc.get_object_id("_id")?.to_string(),
+        user_id: doc.get_str("user_id")?.to_owned(),
+        name: doc.get_str("name")?.to_owned(),
+        prefix: doc.get_str("prefix")?.to_owned(),
+        scopes,
+        created_at: *doc.get_datetime("created_at")?,
+        expires_at: doc.get_datetime("expires_at").ok().copied(),
+        last_used_at: doc.get_datetime("last_used_at").ok().copied(),
+    };
+    Ok(result)
+}
+
+
+// Scopes are permission names, which the owner must have. The user scope grants no permission beyond being
+// authenticated, and the admin scope grants all of the owner's permissions. Keys default to the user scope.
+pub fn validate_scopes(scopes: &[String], owner_permissions: &[String]) -> Result<Vec<String>> {
+    if scopes.is_empty() {
+        return Ok(vec![SCOPE_USER.to_owned()]);
+    }
+    let mut result: Vec<String> = Vec::new();
+    for scope in scopes {
+        let allowed = scope == SCOPE_USER
+            || (scope == SCOPE_ADMIN && !owner_permissions.is_empty())
+            || owner_permissions.contains(scope);
+        if !allowed {
+            return Err(AppError::InvalidApiKeyRequestError);
+        }
+        if !result.contains(scope) {
+            result.push(scope.to_owned());
+        }
+    }
+    Ok(result)
+}
+
+
+// A key never grants more than its owner currently has, so demoting the owner also restricts their keys
+pub fn effective_permissions(api_key: &ApiKey, owner_permissions: &[String]) -> Vec<String> {
+    if api_key.scopes.iter().any(|scope| scope == SCOPE_ADMIN) {
+        return owner_permissions.to_vec();
+    }
+    owner_permissions.iter()
+        .filter(|permission| api_key.scopes.contains(permission))
+        .cloned()
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_to_the_user_scope() {
+        assert_eq!(validate_scopes(&[], &names(&["articles:create"])).unwrap(), names(&[SCOPE_USER]));
+    }
+
+    #[test]
+    fn accepts_scopes_the_owner_holds_once() {
+        let scopes = names(&["articles:create", SCOPE_USER, "articles:create"]);
+        assert_eq!(validate_scopes(&scopes, &names(&["articles:create"])).unwrap(), names(&["articles:create", SCOPE_USER]));
+    }
+
+    #[test]
+    fn refuses_scopes_beyond_the_owner() {
+        let result = validate_scopes(&names(&["users:manage"]), &names(&["articles:create"]));
+        assert!(matches!(result, Err(AppError::InvalidApiKeyRequestError)));
+        let result = validate_scopes(&names(&[SCOPE_ADMIN]), &[]);
+        assert!(matches!(result, Err(AppError::InvalidApiKeyRequestError)));
+    }
+
+    fn api_key(scopes: &[&str]) -> ApiKey {
+        ApiKey {
+            id: "5f1b2c3d4e5f6a7b8c9d0e1f".to_owned(),
+            user_id: "5f1b2c3d4e5f6a7b8c9d0e20".to_owned(),
+            name: "ci".to_owned(),
+            prefix: "abcd".to_owned(),
+            scopes: names(scopes),
+            created_at: chrono::Utc::now(),
+            expires_at: None,
+            last_used_at: None,
+        }
+    }
+
+    #[test]
+    fn grants_the_scopes_the_owner_still_holds() {
+        let owner_permissions = names(&["articles:create", "comments:moderate"]);
+        assert_eq!(effective_permissions(&api_key(&[SCOPE_ADMIN]), &owner_permissions), owner_permissions);
+        assert_eq!(effective_permissions(&api_key(&["comments:moderate", "users:manage"]), &owner_permissions), names(&["comments:moderate"]));
+        assert!(effective_permissions(&api_key(&[SCOPE_USER]), &owner_permissions).is_empty());
+    }
+}
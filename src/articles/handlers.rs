@@ -7,6 +7,8 @@ use crate::environment::Environment;
 use crate::articles::service;
 use crate::WebResult;
 use crate::articles::models::{Article, NewComment, Comment};
+use crate::articles::utils::{can_delete, can_edit, can_set_home};
+use crate::roles::{ARTICLES_CREATE, ARTICLES_WRITE};
 use crate::error::{AppError};
 
 
@@ -27,7 +29,10 @@ pub async fn get_articles_handler(_env: Environment) -> WebResult<impl Reply> {
 }
 
 pub async fn create_article_handler(mut _req: Article, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
-    if _req.in_home == None {
+    if !_user.has_permission(ARTICLES_CREATE) && !_user.has_permission(ARTICLES_WRITE) {
+        return Err(reject::custom(AppError::NoPermissionError));
+    }
+    if _req.in_home.is_none() || !can_set_home(&_user) {
         _req.in_home = Some(false);
     }
     if _req.tags == None {
@@ -35,6 +40,7 @@ pub async fn create_article_handler(mut _req: Article, _env: Environment, _user:
     }
     _req.created_at = Some(Utc::now());
     _req.updated_at = Some(Utc::now());
+    _req.author_id = Some(_user.id.clone());
 
     println!("[create_article_handler] in_home={}", &_req.in_home.clone().unwrap());
     let _result = service::create_article(&_req, _env.db()).await.unwrap();
@@ -43,6 +49,16 @@ pub async fn create_article_handler(mut _req: Article, _env: Environment, _user:
 }
 
 pub async fn update_article_handler(mut _req: Article, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let _id = _req.id.clone().ok_or(reject::custom(AppError::ArticleNotFoundError))?;
+    let existing = service::get_article_by_id(&_id, _env.db()).await.map_err(reject::custom)?;
+    if !can_edit(&_user, &existing) {
+        println!("[update_article_handler] User {} may not edit article {}", _user, &_id);
+        return Err(reject::custom(AppError::NoPermissionError));
+    }
+    // Only editors choose the home page articles, authors keep the current setting
+    if !can_set_home(&_user) {
+        _req.in_home = existing.in_home;
+    }
     if _req.in_home == None {
         _req.in_home = Some(false);
     }
@@ -55,12 +71,20 @@ pub async fn update_article_handler(mut _req: Article, _env: Environment, _user:
 }
 
 pub async fn delete_article_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let existing = service::get_article_by_id(&_id, _env.db()).await.map_err(reject::custom)?;
+    if !can_delete(&_user, &existing) {
+        println!("[delete_article_handler] User {} may not delete article {}", _user, &_id);
+        return Err(reject::custom(AppError::NoPermissionError));
+    }
     println!("[delete_article_handler] id={}", _id.clone());
     let _result = service::delete_article(&_id, _env.db()).await.map_err(|_e| reject::custom(AppError::DataError))?;
     Ok(warp::reply::json(&json!({"status":"success", "message":"Article deleted"})))
 }
 
 pub async fn update_home_view_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    if !can_set_home(&_user) {
+        return Err(reject::custom(AppError::NoPermissionError));
+    }
     println!("[update_home_view_handler] id={}", &_id);
     let _result = service::update_home_view(_id, _env.db()).await.map_err(|_e| reject::custom(AppError::DataError))?;
     Ok(warp::reply::json(&json!({"status":"success", "message":"Article updated"})))
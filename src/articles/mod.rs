@@ -3,3 +3,6 @@ pub mod models;
 pub mod routes;
 pub mod service;
 pub mod utils;
+
+// Shown instead of the name of users whose account was deleted
+pub const DELETED_USER_NAME: &str = "Deleted user";
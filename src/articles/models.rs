@@ -12,6 +12,8 @@ pub struct Article {
     pub updated_at: Option<DateTime<Utc>>,
     pub in_home: Option<bool>,
     pub comments: Option<Vec<Comment>>,
+    // Id of the user who created the article, set by the server. Articles created before authors were tracked have none.
+    pub author_id: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -37,3 +39,12 @@ pub struct Tag {
     pub name: Option<String>,
     pub color: Option<String>,
 }
+
+// Comment along with the article it was posted on
+#[derive(Clone, Serialize, Debug)]
+pub struct PostedComment {
+    pub article_id: Option<String>,
+    pub article_title: Option<String>,
+    #[serde(flatten)]
+    pub comment: Comment,
+}
@@ -2,7 +2,7 @@ use warp::{Filter, Reply};
 use warp::filters::BoxedFilter;
 
 use crate::{auth, environment};
-use crate::auth::models::Role;
+use crate::roles::COMMENTS_MODERATE;
 use crate::environment::Environment;
 use crate::articles::handlers;
 
@@ -22,28 +22,35 @@ pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
     let create_article_route = warp::post().and(warp::path!("api" / "articles")
         .and(warp::body::json())
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::authenticated(_env.clone()))
         .and_then(handlers::create_article_handler));
 
     let update_article_route = warp::put().and(warp::path!("api" / "articles")
         .and(warp::body::json())
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::authenticated(_env.clone()))
         .and_then(handlers::update_article_handler));
 
     let delete_article_route = warp::delete().and(warp::path!("api" / "articles" / String)
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::authenticated(_env.clone()))
         .and_then(handlers::delete_article_handler));
 
-    let update_home_view_route = warp::get().and(warp::path!("api" / "articles" / "updateHomeView" / String)
+    // Changes state, so browsers using cookie sessions have to use PUT, which goes through the CSRF check.
+    // GET is still accepted from clients sending an Authorization header.
+    let update_home_view_route = warp::put().and(warp::path!("api" / "articles" / "updateHomeView" / String)
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::authenticated(_env.clone()))
+        .and_then(handlers::update_home_view_handler));
+
+    let update_home_view_get_route = warp::get().and(warp::path!("api" / "articles" / "updateHomeView" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_authorization_header(_env.clone()))
         .and_then(handlers::update_home_view_handler));
 
     let delete_comment_route = warp::delete().and(warp::path!("api" / "articles" / "comments" / String / String)
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::with_permission(_env.clone(), COMMENTS_MODERATE))
         .and_then(handlers::delete_comment_handler));
 
     let post_comment_route = warp::post().and(warp::path!("api" / "articles" / "comments")
@@ -58,6 +65,7 @@ pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
         .or(update_article_route)
         .or(delete_article_route)
         .or(update_home_view_route)
+        .or(update_home_view_get_route)
         .or(delete_comment_route)
         .or(post_comment_route);
 
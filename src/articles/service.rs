@@ -1,11 +1,15 @@
 use chrono::Utc;
 use mongodb::bson::{doc};
 use mongodb::{Database};
+use mongodb::options::{FindOptions, UpdateOptions};
+use tokio::stream::StreamExt;
 
 use crate::Result;
-use crate::articles::models::{Article, Comment};
-use crate::articles::utils::{parse_articles, parse_article, article_to_doc, comment_to_doc};
+use crate::articles::DELETED_USER_NAME;
+use crate::articles::models::{Article, Comment, PostedComment};
+use crate::articles::utils::{parse_articles, parse_article, article_to_doc, comment_to_doc, doc_to_article};
 use crate::error::{AppError};
+use crate::users::utils::{email_collation, normalize_email};
 
 
 pub async fn get_articles(_db: Database) -> Result<Vec<Article>> {
@@ -38,6 +42,17 @@ pub async fn get_article_by_url(_url: String, _db: Database) -> Result<Article>
 }
 
 
+pub async fn get_article_by_id(_id: &str, _db: Database) -> Result<Article> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::ArticleNotFoundError)?;
+    let filter = doc! { "_id": oid };
+    let mut _cursor = _db.collection("articles").find(filter, None).await.map_err(|_e| {
+        println!("ERROR [get_article_by_id] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    return parse_article(_cursor).await;
+}
+
+
 pub async fn create_article(_article: &Article, _db: Database) -> Result<()> {
     let doc = article_to_doc(_article);
     let _cursor = _db.collection("articles").insert_one(doc, None).await.map_err(|_e| { 
@@ -126,3 +141,65 @@ pub async fn delete_comment(_article_id: String, _comment_id: String, _db: Datab
     })?;
     Ok(())
 }
+
+
+// Comments are not linked to accounts, so the comments of a deleted user are found by their email address,
+// whatever its case, with the collation of the users' email index
+pub async fn anonymize_comments(email: &str, _db: Database) -> Result<()> {
+    let filter = doc! { "comments.email": email };
+    let updates = doc! { "$set": {
+        "comments.$[comment].author": DELETED_USER_NAME,
+        "comments.$[comment].email": "",
+    } };
+    let options = UpdateOptions::builder()
+        .array_filters(vec![doc! { "comment.email": email }])
+        .collation(email_collation())
+        .build();
+    _db.collection("articles").update_many(filter, updates, options).await.map_err(|_e| {
+        println!("ERROR [anonymize_comments] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Returns the comments posted with the given email address, whatever its case
+pub async fn get_comments_by_email(email: &str, _db: Database) -> Result<Vec<PostedComment>> {
+    let options = FindOptions::builder().collation(email_collation()).build();
+    let mut _cursor = _db.collection("articles").find(doc! { "comments.email": email }, options).await.map_err(|_e| {
+        println!("ERROR [get_comments_by_email] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<PostedComment> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        let article = doc_to_article(&doc?, true)?;
+        for comment in article.comments.unwrap_or_default() {
+            if normalize_email(&comment.email) == normalize_email(email) {
+                result.push(PostedComment { article_id: article.id.clone(), article_title: article.title.clone(), comment });
+            }
+        }
+    }
+    Ok(result)
+}
+
+
+// Gives the articles of one user to another, when merging accounts
+pub async fn reassign_author(from_user_id: &str, to_user_id: &str, _db: Database) -> Result<()> {
+    let updates = doc! { "$set": { "author_id": to_user_id } };
+    _db.collection("articles").update_many(doc! { "author_id": from_user_id }, updates, None).await.map_err(|_e| {
+        println!("ERROR [reassign_author] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Forgets the author of the articles created by the user, which are kept
+pub async fn clear_author(user_id: &str, _db: Database) -> Result<()> {
+    let updates = doc! { "$unset": { "author_id": "" } };
+    _db.collection("articles").update_many(doc! { "author_id": user_id }, updates, None).await.map_err(|_e| {
+        println!("ERROR [clear_author] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
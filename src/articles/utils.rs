@@ -2,6 +2,8 @@ use mongodb::bson::{Bson, doc};
 use tokio::stream::StreamExt;
 
 use crate::articles::models::{Article, Comment};
+use crate::auth::models::AuthUser;
+use crate::roles::{ARTICLES_CREATE, ARTICLES_EDIT, ARTICLES_WRITE};
 use crate::Result;
 use crate::error::{AppError};
 
@@ -25,7 +27,7 @@ pub async fn parse_article(mut _cursor: mongodb::Cursor) -> Result<Article> {
 
 
 pub fn article_to_doc(_article: &Article) -> mongodb::bson::document::Document {
-    doc! {
+    let mut doc = doc! {
         "title": _article.title.clone().unwrap(),
         "url": _article.url.clone().unwrap(),
         "content": _article.content.clone().unwrap(),
@@ -34,7 +36,11 @@ pub fn article_to_doc(_article: &Article) -> mongodb::bson::document::Document {
         "created_at": _article.created_at.clone().unwrap(),
         "updated_at": _article.updated_at.clone().unwrap(),
         "comments": []
+    };
+    if let Some(author_id) = &_article.author_id {
+        doc.insert("author_id", author_id);
     }
+    return doc;
 }
 
 
@@ -51,6 +57,7 @@ pub fn doc_to_article(_doc: &mongodb::bson::document::Document, _include_content
     let tags = _doc.get_array("tags")?;
     let created_at = _doc.get_datetime("created_at")?;
     let updated_at = _doc.get_datetime("updated_at")?;
+    let author_id = _doc.get_str("author_id").ok();
 
     let mut comments = Vec::<Comment>::new();
     match _include_content {
@@ -80,6 +87,7 @@ pub fn doc_to_article(_doc: &mongodb::bson::document::Document, _include_content
         created_at: Some(*created_at),
         updated_at: Some(*updated_at),
         in_home: Some(in_home),
+        author_id: author_id.map(str::to_owned),
     };
     Ok(result)
 }
@@ -106,3 +114,25 @@ pub fn doc_to_comment(_doc: &mongodb::bson::document::Document) -> Result<Commen
     };
     Ok(comment)
 }
+
+
+// Authors may only edit their own articles, while editors may edit any article
+pub fn can_edit(_user: &AuthUser, _article: &Article) -> bool {
+    _user.has_permission(ARTICLES_WRITE) || _user.has_permission(ARTICLES_EDIT) || is_own(_user, _article)
+}
+
+
+// Editors cannot delete articles written by others
+pub fn can_delete(_user: &AuthUser, _article: &Article) -> bool {
+    _user.has_permission(ARTICLES_WRITE) || is_own(_user, _article)
+}
+
+
+pub fn can_set_home(_user: &AuthUser) -> bool {
+    _user.has_permission(ARTICLES_WRITE) || _user.has_permission(ARTICLES_EDIT)
+}
+
+
+fn is_own(_user: &AuthUser, _article: &Article) -> bool {
+    _user.has_permission(ARTICLES_CREATE) && _article.author_id.as_deref() == Some(_user.id.as_str())
+}
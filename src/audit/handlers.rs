@@ -0,0 +1,14 @@
+use warp::{Reply, reject};
+
+use crate::WebResult;
+use crate::audit::models::AuditQuery;
+use crate::audit::service;
+use crate::auth::models::AuthUser;
+use crate::environment::Environment;
+
+// Returns the latest audit log entries, optionally for a single user or action
+pub async fn get_audit_log_handler(_query: AuditQuery, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    println!("[get_audit_log_handler] Action performed by user {} ({})", _user.id, _user.role);
+    let result = service::get_entries(&_env, &_query).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
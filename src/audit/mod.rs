@@ -0,0 +1,18 @@
+pub mod handlers;
+pub mod models;
+pub mod routes;
+pub mod service;
+pub mod utils;
+
+// Recorded actions
+pub const IMPERSONATION_START: &str = "impersonation_start";
+pub const IMPERSONATION_STOP: &str = "impersonation_stop";
+pub const IMPERSONATION_REQUEST: &str = "impersonation_request";
+pub const USER_DISABLED: &str = "user_disabled";
+pub const USER_ENABLED: &str = "user_enabled";
+pub const USER_DELETED: &str = "user_deleted";
+pub const USERS_IMPORTED: &str = "users_imported";
+pub const USERS_EXPORTED: &str = "users_exported";
+pub const PERSONAL_DATA_EXPORTED: &str = "personal_data_exported";
+pub const USER_ERASED: &str = "user_erased";
+pub const USERS_MERGED: &str = "users_merged";
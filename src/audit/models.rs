@@ -0,0 +1,20 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Entry of the append-only audit log. `actor_id` performed `action`, on behalf of or on `user_id`.
+#[derive(Clone, Serialize, Debug)]
+pub struct AuditEntry {
+    pub id: Option<String>,
+    pub action: String,
+    pub actor_id: Option<String>,
+    pub user_id: Option<String>,
+    pub details: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    // Entries where the user is either the actor or the subject
+    pub user_id: Option<String>,
+    pub action: Option<String>,
+}
@@ -0,0 +1,17 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::audit::handlers;
+use crate::environment::Environment;
+use crate::roles::AUDIT_READ;
+
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    let get_audit_log_route = warp::get().and(warp::path!("api" / "audit")
+        .and(warp::query())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), AUDIT_READ))
+        .and_then(handlers::get_audit_log_handler));
+
+    get_audit_log_route.boxed()
+}
@@ -0,0 +1,98 @@
+use chrono::Utc;
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use tokio::stream::StreamExt;
+
+use crate::Result;
+use crate::audit::models::{AuditEntry, AuditQuery};
+use crate::audit::utils::{doc_to_entry, entry_to_doc};
+use crate::environment::Environment;
+use crate::error::AppError;
+
+// Number of entries returned by a query, newest first
+const QUERY_LIMIT: i64 = 500;
+
+
+pub async fn create_indexes(_env: &Environment) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "audit_log",
+        "indexes": [
+            { "key": { "created_at": -1 }, "name": "created_at" },
+            { "key": { "actor_id": 1, "created_at": -1 }, "name": "actor_id" },
+            { "key": { "user_id": 1, "created_at": -1 }, "name": "user_id" },
+        ]
+    };
+    _env.db().run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [audit::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Callers fail when the entry cannot be written, so that audited actions never go unrecorded
+pub async fn record(_env: &Environment, action: &str, actor_id: Option<&str>, user_id: Option<&str>, details: Option<String>) -> Result<()> {
+    let entry = AuditEntry {
+        id: None,
+        action: action.to_owned(),
+        actor_id: actor_id.map(str::to_owned),
+        user_id: user_id.map(str::to_owned),
+        details,
+        created_at: Utc::now(),
+    };
+    println!("[audit] {} actor={:?} user={:?} {}", &entry.action, &entry.actor_id, &entry.user_id, entry.details.as_deref().unwrap_or(""));
+    _env.db().collection("audit_log").insert_one(entry_to_doc(&entry), None).await.map_err(|_e| {
+        println!("ERROR [audit::record] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+pub async fn get_entries(_env: &Environment, _query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+    let mut filter = Document::new();
+    if let Some(user_id) = &_query.user_id {
+        filter.insert("$or", vec![doc! { "actor_id": user_id }, doc! { "user_id": user_id }]);
+    }
+    if let Some(action) = &_query.action {
+        filter.insert("action", action);
+    }
+    let options = FindOptions::builder().sort(doc! { "created_at": -1 }).limit(QUERY_LIMIT).build();
+    let mut _cursor = _env.db().collection("audit_log").find(filter, options).await.map_err(|_e| {
+        println!("ERROR [audit::get_entries] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<AuditEntry> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        result.push(doc_to_entry(&doc?)?);
+    }
+    Ok(result)
+}
+
+
+// Returns every entry where the user is either the actor or the subject, oldest first
+pub async fn get_user_entries(_env: &Environment, user_id: &str) -> Result<Vec<AuditEntry>> {
+    let filter = doc! { "$or": [{ "actor_id": user_id }, { "user_id": user_id }] };
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    let mut _cursor = _env.db().collection("audit_log").find(filter, options).await.map_err(|_e| {
+        println!("ERROR [audit::get_user_entries] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<AuditEntry> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        result.push(doc_to_entry(&doc?)?);
+    }
+    Ok(result)
+}
+
+
+// Removes the details, which may hold personal data, of the entries about the user. The entries themselves are kept.
+pub async fn redact_user_entries(_env: &Environment, user_id: &str) -> Result<()> {
+    let filter = doc! { "$or": [{ "actor_id": user_id }, { "user_id": user_id }] };
+    let updates = doc! { "$unset": { "details": "" } };
+    _env.db().collection("audit_log").update_many(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [audit::redact_user_entries] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use mongodb::bson::{doc, Document};
+
+use crate::Result;
+use crate::audit::models::AuditEntry;
+
+
+pub fn entry_to_doc(_entry: &AuditEntry) -> Document {
+    let mut doc = doc! {
+        "action": &_entry.action,
+        "created_at": _entry.created_at,
+    };
+    if let Some(actor_id) = &_entry.actor_id {
+        doc.insert("actor_id", actor_id);
+    }
+    if let Some(user_id) = &_entry.user_id {
+        doc.insert("user_id", user_id);
+    }
+    if let Some(details) = &_entry.details {
+        doc.insert("details", details);
+    }
+    return doc;
+}
+
+
+pub fn doc_to_entry(doc: &Document) -> Result<AuditEntry> {
+    let result = AuditEntry {
+        id: Some(doc.get_object_id("_id")?.to_string()),
+        action: doc.get_str("action")?.to_owned(),
+        actor_id: doc.get_str("actor_id").ok().map(str::to_owned),
+        user_id: doc.get_str("user_id").ok().map(str::to_owned),
+        details: doc.get_str("details").ok().map(str::to_owned),
+        created_at: *doc.get_datetime("created_at")?,
+    };
+    Ok(result)
+}
@@ -0,0 +1,173 @@
+use warp::Reply;
+use warp::http::{HeaderMap, HeaderValue, Method};
+use warp::http::header::{COOKIE, SET_COOKIE};
+use warp::reply::Response;
+
+use crate::Result;
+use crate::auth::{ACCESS_TOKEN_COOKIE, CSRF_COOKIE, CSRF_HEADER, OIDC_STATE_COOKIE, OIDC_STATE_TTL, REFRESH_TOKEN_COOKIE};
+use crate::auth::models::LoginResponse;
+use crate::auth::utils::generate_token;
+use crate::environment::Environment;
+use crate::error::AppError;
+
+// Refresh tokens are only sent to the auth routes that use them
+const REFRESH_TOKEN_PATH: &str = "/api/auth";
+const OIDC_STATE_PATH: &str = "/api/auth/oidc";
+
+
+// Value of the named cookie of the request, if any
+pub fn cookie_value(headers: &HeaderMap<HeaderValue>, name: &str) -> Option<String> {
+    headers.get_all(COOKIE).iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_owned())
+}
+
+
+// Double-submit check for requests authenticated by cookie: a page of another site can make the browser send
+// our cookies, but cannot read the CSRF cookie to repeat it in the header.
+pub fn check_csrf(headers: &HeaderMap<HeaderValue>, method: &Method) -> Result<()> {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+    let cookie = cookie_value(headers, CSRF_COOKIE).unwrap_or_default();
+    let header = headers.get(CSRF_HEADER).and_then(|header| header.to_str().ok()).unwrap_or_default();
+    if cookie.is_empty() || !constant_time_eq(cookie.as_bytes(), header.as_bytes()) {
+        return Err(AppError::CsrfTokenError);
+    }
+    Ok(())
+}
+
+
+// In cookie session mode the tokens are moved from the body to HttpOnly cookies, along with a new CSRF token
+pub fn session_reply(_env: &Environment, mut body: LoginResponse) -> Response {
+    if !_env.config().cookie_sessions {
+        return warp::reply::json(&body).into_response();
+    }
+    let cookies = vec![
+        cookie(_env, ACCESS_TOKEN_COOKIE, &body.access_token, "/", _env.config().access_token_ttl, true, "Strict"),
+        cookie(_env, REFRESH_TOKEN_COOKIE, &body.refresh_token, REFRESH_TOKEN_PATH, _env.config().refresh_token_ttl, true, "Strict"),
+        cookie(_env, CSRF_COOKIE, &generate_token(), "/", _env.config().refresh_token_ttl, false, "Strict"),
+    ];
+    body.access_token.clear();
+    body.refresh_token.clear();
+    with_cookies(warp::reply::json(&body), cookies)
+}
+
+
+// Expires the session cookies, if cookie sessions are enabled
+pub fn clear_session(_env: &Environment, reply: impl Reply) -> Response {
+    if !_env.config().cookie_sessions {
+        return reply.into_response();
+    }
+    let cookies = vec![
+        cookie(_env, ACCESS_TOKEN_COOKIE, "", "/", 0, true, "Strict"),
+        cookie(_env, REFRESH_TOKEN_COOKIE, "", REFRESH_TOKEN_PATH, 0, true, "Strict"),
+        cookie(_env, CSRF_COOKIE, "", "/", 0, false, "Strict"),
+    ];
+    with_cookies(reply, cookies)
+}
+
+
+// Binds the single sign-on state to the browser that started the flow, so that a callback URL made for another
+// browser is refused. SameSite=Lax, as the provider redirects back with a cross-site navigation.
+pub fn with_oidc_state(_env: &Environment, reply: impl Reply, state: &str) -> Response {
+    with_cookies(reply, vec![cookie(_env, OIDC_STATE_COOKIE, state, OIDC_STATE_PATH, OIDC_STATE_TTL, true, "Lax")])
+}
+
+
+pub fn check_oidc_state(headers: &HeaderMap<HeaderValue>, state: &str) -> Result<()> {
+    let cookie = cookie_value(headers, OIDC_STATE_COOKIE).unwrap_or_default();
+    if cookie.is_empty() || !constant_time_eq(cookie.as_bytes(), state.as_bytes()) {
+        return Err(AppError::OidcError);
+    }
+    Ok(())
+}
+
+
+pub fn clear_oidc_state(_env: &Environment, reply: impl Reply) -> Response {
+    with_cookies(reply, vec![cookie(_env, OIDC_STATE_COOKIE, "", OIDC_STATE_PATH, 0, true, "Lax")])
+}
+
+
+fn cookie(_env: &Environment, name: &str, value: &str, path: &str, max_age: i64, http_only: bool, same_site: &str) -> String {
+    let mut cookie = format!("{}={}; Path={}; Max-Age={}; SameSite={}", name, value, path, max_age, same_site);
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if _env.config().cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+
+fn with_cookies(reply: impl Reply, cookies: Vec<String>) -> Response {
+    let mut response = reply.into_response();
+    for cookie in cookies {
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(cookie: Option<&str>, header: Option<&str>) -> HeaderMap<HeaderValue> {
+        let mut headers = HeaderMap::new();
+        if let Some(cookie) = cookie {
+            headers.insert(COOKIE, HeaderValue::from_str(&format!("theme=dark; {}={}", CSRF_COOKIE, cookie)).unwrap());
+        }
+        if let Some(header) = header {
+            headers.insert(CSRF_HEADER, HeaderValue::from_str(header).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn lets_safe_methods_through() {
+        assert!(check_csrf(&headers(None, None), &Method::GET).is_ok());
+        assert!(check_csrf(&headers(None, None), &Method::HEAD).is_ok());
+        assert!(check_csrf(&headers(None, None), &Method::OPTIONS).is_ok());
+    }
+
+    #[test]
+    fn accepts_a_header_matching_the_cookie() {
+        assert!(check_csrf(&headers(Some("abc123"), Some("abc123")), &Method::POST).is_ok());
+        assert!(check_csrf(&headers(Some("abc123"), Some("abc123")), &Method::PUT).is_ok());
+    }
+
+    #[test]
+    fn refuses_a_missing_or_different_token() {
+        for headers in [headers(None, None), headers(Some("abc123"), None), headers(None, Some("abc123")), headers(Some("abc123"), Some("abc124")), headers(Some(""), Some(""))].iter() {
+            assert!(matches!(check_csrf(headers, &Method::POST), Err(AppError::CsrfTokenError)));
+        }
+    }
+
+    #[test]
+    fn reads_the_named_cookie() {
+        let headers = headers(Some("abc123"), None);
+        assert_eq!(cookie_value(&headers, CSRF_COOKIE), Some("abc123".to_owned()));
+        assert_eq!(cookie_value(&headers, ACCESS_TOKEN_COOKIE), None);
+    }
+
+    #[test]
+    fn binds_the_single_sign_on_state_to_its_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(&format!("{}=state123", OIDC_STATE_COOKIE)).unwrap());
+        assert!(check_oidc_state(&headers, "state123").is_ok());
+        assert!(matches!(check_oidc_state(&headers, "state124"), Err(AppError::OidcError)));
+        assert!(matches!(check_oidc_state(&HeaderMap::new(), ""), Err(AppError::OidcError)));
+    }
+}
@@ -1,49 +1,74 @@
+use std::net::SocketAddr;
+
 use warp::Reply;
+use warp::http::{HeaderMap, HeaderValue, Method, Uri};
 use serde_json::json;
 use warp::reject;
 use chrono::Utc;
 
-use crate::{users, WebResult};
-use crate::auth::create_jwt;
-use crate::auth::models::{LoginRequest, LoginResponse, Role};
+use crate::{audit, auth, invitations, roles, sessions, users, Result, WebResult};
+use crate::auth::{cookies, create_impersonation_jwt, create_jwt, create_mfa_token, decode_jwt, oidc, revocation, send_verification_email, throttle, totp, EMAIL_VERIFICATION, MFA_PURPOSE, MFA_RECOVERY_CODES, OIDC_STATE_TTL, PASSWORD_RESET, REFRESH_TOKEN_COOKIE};
+use crate::auth::utils::generate_token;
+use crate::auth::models::{AuthUser, ForgotPasswordRequest, ImpersonationResponse, LoginRequest, LoginResponse, Mfa, MfaChallenge, MfaCodeRequest, MfaEnrollResponse, MfaLoginRequest, MfaRecoveryCodesResponse, OidcCallbackQuery, OidcState, RefreshRequest, RegisterRequest, ResendVerificationRequest, ResetPasswordRequest, Role, VerifyEmailQuery};
 use crate::environment::Environment;
 use crate::error::{AppError};
 use crate::users::models::{User};
 
-pub async fn register_handler(mut _req: User, _env: Environment) -> WebResult<impl Reply> {
-    // match users::service::get_user_by_email(&_req.email, _env.db()).await {
-    //     Ok(None) => (),
-    //     Ok(existing) => {
-    //         println!("[register_handler] User {} already exists", &existing.unwrap().email);
-    //         return Ok(warp::reply::json(&json!({"status":"error", "message":"Unable to complete registration, email already registered"})))
-    //     },
-    //     _ => (),
-    // }
-
-    let hash = _env.argon().hasher().with_password(&_req.password.unwrap()).hash().unwrap();
-    _req.password = Some(hash);
-    _req.role = Some(Role::User);
-    _req.created_at = Some(Utc::now());
-    _req.updated_at = Some(Utc::now());
-
-    let email = _req.email.clone();
-    let _res = users::service::create_user(_req, _env.db()).await.map_err(|e| reject::custom(e));
+pub async fn register_handler(_req: RegisterRequest, _env: Environment) -> WebResult<impl Reply> {
+    let mut user = _req.user;
+    let password = user.password.clone().unwrap_or_default();
+    _env.password_policy().validate(&password, &user.email, &user.name).map_err(reject::custom)?;
+
+    // The invitation decides the role of the new account
+    let invitation = match &_req.invitation_code {
+        Some(code) => Some(invitations::service::use_invitation(code, &user.email, _env.db()).await.map_err(reject::custom)?),
+        None if _env.config().invite_only_registration => {
+            println!("[register_handler] Registration without invitation refused for {}", &user.email);
+            return Err(reject::custom(AppError::InvitationRequiredError));
+        },
+        None => None,
+    };
+
+    let hash = _env.argon().hasher().with_password(&password).hash().unwrap();
+    user.password = Some(hash);
+    user.role = Some(invitation.as_ref().map(|i| i.role.clone()).unwrap_or(Role::User));
+    user.created_at = Some(Utc::now());
+    user.updated_at = Some(Utc::now());
+    user.verified_at = None;
+
+    let email = user.email.clone();
+    let name = user.name.clone();
+    // Duplicate email addresses are refused by the unique index on users.email
+    let _res = users::service::create_user(user, _env.db()).await;
     match _res {
-        Ok(()) => {
+        Ok(id) => {
             println!("[register_handler] Registration successful: {:?}", &email);
+            send_verification_email(&_env, &id, &email, &name).await.map_err(reject::custom)?;
             return Ok(warp::reply::json(&json!({"status": "success"})));
         },
         Err(_e) => {
-            println!("[register_handler] Error registering user {}", &email);
+            println!("[register_handler] Error registering user {}: {}", &email, _e);
+            if let Some(invitation) = invitation {
+                invitations::service::release_invitation(&invitation.id, _env.db()).await.map_err(reject::custom)?;
+            }
+            if let AppError::EmailExistsError = _e {
+                return Err(reject::custom(_e));
+            }
             return Ok(warp::reply::json(&json!({"status":"error", "message":"Registration error"})))
         }
     }
 }
 
-pub async fn login_handler(_req: LoginRequest, _env: Environment) -> WebResult<impl Reply> {
+pub async fn login_handler(_req: LoginRequest, _env: Environment, addr: Option<SocketAddr>, headers: HeaderMap<HeaderValue>) -> WebResult<impl Reply> {
+    let account_key = throttle::account_key(&_req.email);
+    let mut throttle_keys = vec![account_key.clone()];
+    throttle_keys.extend(throttle::ip_key(addr, &headers, &_env));
+    throttle::reserve(&_env, &throttle_keys).await.map_err(reject::custom)?;
+
     let user_option = match users::service::get_user_by_email(&_req.email, _env.db()).await {
         Err(_e) => {
             println!("[login_handler] Error authenticating user {:?}. {:?}", &_req.email, _e);
+            throttle::record_failure(&_env, &throttle_keys).await.map_err(reject::custom)?;
             return Err(warp::reject::custom(AppError::WrongCredentialsError))
         },
         Ok(existing) => existing,
@@ -54,22 +79,437 @@ pub async fn login_handler(_req: LoginRequest, _env: Environment) -> WebResult<i
     };
 
     let user = user_option;
+    let password_hash = match &user.password {
+        Some(hash) => hash.clone(),
+        None => {
+            println!("[login_handler] User {:?} has no password and signs in through single sign-on", &_req.email);
+            throttle::record_failure(&_env, &throttle_keys).await.map_err(reject::custom)?;
+            return Err(warp::reject::custom(AppError::WrongCredentialsError))
+        },
+    };
     let is_valid = _env
         .argon()
         .verifier()
-        .with_hash(&user.password.clone().unwrap())
+        .with_hash(&password_hash)
         .with_password(&_req.password)
         .verify()
         .or(Err(warp::reject::custom(AppError::ArgonError)))?;
 
     if !is_valid {
         println!("[login_handler] Invalid credentials for user {:?}", &_req.email);
+        throttle::record_failure(&_env, &throttle_keys).await.map_err(reject::custom)?;
         return Err(warp::reject::custom(AppError::WrongCredentialsError))
     }
+    throttle::release(&_env, &throttle_keys).await.map_err(reject::custom)?;
+
+    if user.disabled_at.is_some() {
+        println!("[login_handler] Login of disabled user {:?} refused", &_req.email);
+        return Err(warp::reject::custom(AppError::AccountDisabledError))
+    }
+
+    let mut user = user;
+    if _env.argon().needs_rehash(&password_hash) {
+        upgrade_password_hash(&mut user, &_req.password, &_env).await;
+    }
+
+    if _env.config().require_verified_email && user.verified_at.is_none() {
+        println!("[login_handler] Email not verified for user {:?}", &_req.email);
+        return Err(warp::reject::custom(AppError::EmailNotVerifiedError))
+    }
 
     let role = &user.role.clone().unwrap();
+    let user_id = user.id.clone().unwrap();
+    let mfa = auth::service::get_mfa(&_env, &user_id).await.map_err(reject::custom)?;
+    if matches!(mfa, Some(m) if m.enabled_at.is_some()) {
+        println!("[login_handler] Password verified for user '{}', awaiting second factor", &user.email);
+        let mfa_token = create_mfa_token(&_env, &user_id, role).map_err(reject::custom)?;
+        return Ok(warp::reply::json(&MfaChallenge { mfa_required: true, mfa_token }).into_response());
+    }
+
     println!("[login_handler] Authenticated user '{}' ({})", &user.email.clone(), &role);
-    let token = create_jwt(&user.id.clone().unwrap().to_string(), &Role::from_str(&role.to_string())).unwrap();
-    let body = LoginResponse::from_user(user, token);
-    return Ok(warp::reply::json(&body));
+    throttle::reset(&_env, &account_key).await.map_err(reject::custom)?;
+    let body = start_session(user, addr, &headers, &_env).await.map_err(reject::custom)?;
+    return Ok(cookies::session_reply(&_env, body));
+}
+
+// Re-hashes the password with the current argon parameters. Failures are logged and the login goes on.
+async fn upgrade_password_hash(user: &mut User, password: &str, _env: &Environment) {
+    let hash = match _env.argon().hasher().with_password(password).hash() {
+        Ok(hash) => hash,
+        Err(_e) => {
+            println!("ERROR [upgrade_password_hash] {:?}", _e);
+            return;
+        },
+    };
+    let user_id = user.id.clone().unwrap_or_default();
+    let old_hash = user.password.clone().unwrap_or_default();
+    // Only replaces the hash that was verified, so that a password changed meanwhile is not overwritten
+    match users::service::replace_password_hash(&user_id, &old_hash, &hash, _env.db()).await {
+        Ok(true) => {
+            println!("[upgrade_password_hash] Upgraded password hash of user '{}'", &user.email);
+            _env.metrics().record_password_rehash();
+            user.password = Some(hash);
+        },
+        Ok(false) => println!("[upgrade_password_hash] Password of user '{}' changed meanwhile, hash kept", &user.email),
+        Err(_e) => println!("ERROR [upgrade_password_hash] {:?}", _e),
+    }
+}
+
+// Exchanges a refresh token for a new access token, rotating the refresh token within its family
+pub async fn refresh_handler(_req: RefreshRequest, _env: Environment, headers: HeaderMap<HeaderValue>) -> WebResult<impl Reply> {
+    let token = match cookies::cookie_value(&headers, REFRESH_TOKEN_COOKIE) {
+        Some(token) if _req.refresh_token.is_empty() && _env.config().cookie_sessions => {
+            cookies::check_csrf(&headers, &Method::POST).map_err(reject::custom)?;
+            token
+        },
+        _ => _req.refresh_token,
+    };
+    let refresh_token = auth::service::use_refresh_token(&_env, &token).await.map_err(reject::custom)?;
+    let user = users::service::get_user_by_id(refresh_token.user_id.clone(), _env.db()).await.map_err(|_e| {
+        println!("[refresh_handler] Unable to load user {}: {:?}", &refresh_token.user_id, _e);
+        reject::custom(AppError::RefreshTokenError)
+    })?;
+    println!("[refresh_handler] Refreshed session for user '{}'", &user.email);
+    let body = issue_tokens(user, &refresh_token.family_id, &_env).await.map_err(reject::custom)?;
+    let expires_at = Utc::now() + chrono::Duration::seconds(_env.config().refresh_token_ttl);
+    sessions::service::touch_session(&refresh_token.family_id, &refresh_token.user_id, expires_at, _env.db()).await.map_err(reject::custom)?;
+    Ok(cookies::session_reply(&_env, body))
+}
+
+// Revokes the current access token and ends its session
+pub async fn logout_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    revocation::revoke_token(&_env, &_user).await.map_err(reject::custom)?;
+    if !_user.session_id.is_empty() {
+        match sessions::service::end_session(&_env, &_user.id, &_user.session_id).await {
+            Ok(()) | Err(AppError::SessionNotFoundError) => (),
+            Err(e) => return Err(reject::custom(e)),
+        }
+        auth::service::revoke_refresh_token_family(&_user.session_id, _env.db()).await.map_err(reject::custom)?;
+    }
+    println!("[logout_handler] User {} logged out", _user);
+    Ok(cookies::clear_session(&_env, warp::reply::json(&json!({"status":"success", "message":"Logged out"}))))
+}
+
+// Revokes every access and refresh token of the given user, who may not have more permissions than the caller
+pub async fn revoke_user_sessions_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let target = users::service::get_user_by_id(_id.clone(), _env.db()).await.map_err(reject::custom)?;
+    roles::service::ensure_role_within(&_env, &_user, &target.role.unwrap_or(Role::User)).await.map_err(reject::custom)?;
+    users::service::sign_out_everywhere(&_env, &_id).await.map_err(reject::custom)?;
+    println!("[revoke_user_sessions_handler] Sessions of user {} revoked by {}", &_id, _user);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Sessions revoked"})))
+}
+
+// Issues a short-lived token to act as another user. The admin's own identity travels in the `act` claim.
+pub async fn impersonate_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    if _user.api_key_id.is_some() || _user.actor.is_some() || _user.id == _id {
+        return Err(reject::custom(AppError::NoPermissionError));
+    }
+    let target = users::service::get_user_by_id(_id.clone(), _env.db()).await.map_err(reject::custom)?;
+    if target.disabled_at.is_some() {
+        return Err(reject::custom(AppError::AccountDisabledError));
+    }
+    let role = target.role.clone().unwrap_or(Role::User);
+    // Impersonation is meant to look at regular accounts, not to borrow the identity of an admin or of anyone
+    // with permissions the actor does not have
+    if role == Role::Admin {
+        println!("[impersonate_handler] User {} may not impersonate admin {}", _user, &_id);
+        return Err(reject::custom(AppError::NoPermissionError));
+    }
+    roles::service::ensure_role_within(&_env, &_user, &role).await.map_err(reject::custom)?;
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(_env.config().impersonation_ttl);
+    audit::service::record(&_env, audit::IMPERSONATION_START, Some(&_user.id), Some(&_id), None).await.map_err(reject::custom)?;
+    let access_token = create_impersonation_jwt(&_env, &_id, &role, &_user.id, expires_at).map_err(reject::custom)?;
+    Ok(warp::reply::json(&ImpersonationResponse {
+        id: _id,
+        email: target.email,
+        name: target.name,
+        access_token,
+        expires_at,
+    }))
+}
+
+// Ends an impersonated session by revoking its token
+pub async fn stop_impersonation_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let actor = match &_user.actor {
+        Some(actor) => actor.clone(),
+        None => return Err(reject::custom(AppError::NoPermissionError)),
+    };
+    revocation::revoke_token(&_env, &_user).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::IMPERSONATION_STOP, Some(&actor), Some(&_user.id), None).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Impersonation stopped"})))
+}
+
+// Emails a password reset link. The response is the same whether or not the email is registered.
+pub async fn forgot_password_handler(_req: ForgotPasswordRequest, _env: Environment) -> WebResult<impl Reply> {
+    match users::service::get_user_by_email(&_req.email, _env.db()).await {
+        Ok(user) => {
+            auth::send_password_reset_email(&_env, &user.id.clone().unwrap(), &user.email, &user.name).await.map_err(reject::custom)?;
+            println!("[forgot_password_handler] Password reset requested for {}", &user.email);
+        },
+        Err(_e) => println!("[forgot_password_handler] No reset sent for {:?}: {:?}", &_req.email, _e),
+    }
+    Ok(warp::reply::json(&json!({"status":"success", "message":"If the email is registered, a password reset link has been sent"})))
+}
+
+// Sets a new password with a reset token and signs the user out everywhere
+pub async fn reset_password_handler(_req: ResetPasswordRequest, _env: Environment) -> WebResult<impl Reply> {
+    // The token is only used up once the new password is accepted, so that a rejected password can be retried
+    let user_id = auth::service::find_user_token(&_req.token, PASSWORD_RESET, _env.db()).await.map_err(reject::custom)?;
+    let mut user = users::service::get_user_by_id(user_id.clone(), _env.db()).await.map_err(reject::custom)?;
+    _env.password_policy().validate(&_req.new_password, &user.email, &user.name).map_err(reject::custom)?;
+    auth::service::consume_user_token(&_req.token, PASSWORD_RESET, _env.db()).await.map_err(reject::custom)?;
+
+    let hash = _env.argon().hasher().with_password(&_req.new_password).hash().or(Err(reject::custom(AppError::ArgonError)))?;
+    user.password = Some(hash);
+    users::service::update_user_password(user, _env.db()).await.map_err(reject::custom)?;
+
+    users::service::sign_out_everywhere(&_env, &user_id).await.map_err(reject::custom)?;
+    println!("[reset_password_handler] Password reset for user {}", &user_id);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Password updated"})))
+}
+
+// Confirms the email address with the token from the verification link
+pub async fn verify_email_handler(_query: VerifyEmailQuery, _env: Environment) -> WebResult<impl Reply> {
+    let user_id = auth::service::consume_user_token(&_query.token, EMAIL_VERIFICATION, _env.db()).await.map_err(reject::custom)?;
+    users::service::set_user_verified(&user_id, _env.db()).await.map_err(reject::custom)?;
+    println!("[verify_email_handler] Email verified for user {}", &user_id);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Email verified"})))
+}
+
+// Sends a new verification link to an unverified account, without revealing whether the email is registered
+pub async fn resend_verification_handler(_req: ResendVerificationRequest, _env: Environment) -> WebResult<impl Reply> {
+    match users::service::get_user_by_email(&_req.email, _env.db()).await {
+        Ok(user) if user.verified_at.is_none() => {
+            send_verification_email(&_env, &user.id.clone().unwrap(), &user.email, &user.name).await.map_err(reject::custom)?;
+        },
+        Ok(_) => println!("[resend_verification_handler] {:?} is already verified", &_req.email),
+        Err(_e) => println!("[resend_verification_handler] No verification sent for {:?}: {:?}", &_req.email, _e),
+    }
+    Ok(warp::reply::json(&json!({"status":"success", "message":"If the email is registered and not yet verified, a verification link has been sent"})))
+}
+
+// Completes a login that requires a second factor, with a TOTP code or a recovery code
+pub async fn mfa_login_handler(_req: MfaLoginRequest, _env: Environment, addr: Option<SocketAddr>, headers: HeaderMap<HeaderValue>) -> WebResult<impl Reply> {
+    let claims = decode_jwt(&_env, &_req.mfa_token).map_err(reject::custom)?;
+    if claims.purpose.as_deref() != Some(MFA_PURPOSE) || revocation::is_revoked(&_env, &claims).await.map_err(reject::custom)? {
+        return Err(reject::custom(AppError::JWTTokenError));
+    }
+    let pending = AuthUser::from_claims(claims);
+    let user = users::service::get_user_by_id(pending.id.clone(), _env.db()).await.map_err(reject::custom)?;
+
+    // Guessing codes counts as failed logins, like guessing passwords
+    let account_key = throttle::account_key(&user.email);
+    let mut throttle_keys = vec![account_key.clone()];
+    throttle_keys.extend(throttle::ip_key(addr, &headers, &_env));
+    throttle::reserve(&_env, &throttle_keys).await.map_err(reject::custom)?;
+
+    let mfa = match auth::service::get_mfa(&_env, &pending.id).await.map_err(reject::custom)? {
+        Some(mfa) if mfa.enabled_at.is_some() => mfa,
+        _ => return Err(reject::custom(AppError::JWTTokenError)),
+    };
+    if !check_mfa_code(&mfa, &_req.code, &_env).await.map_err(reject::custom)? {
+        println!("[mfa_login_handler] Invalid second factor for user {}", &pending.id);
+        throttle::record_failure(&_env, &throttle_keys).await.map_err(reject::custom)?;
+        return Err(reject::custom(AppError::MfaCodeError));
+    }
+    throttle::release(&_env, &throttle_keys).await.map_err(reject::custom)?;
+
+    // The challenge token is single use
+    revocation::revoke_token(&_env, &pending).await.map_err(reject::custom)?;
+    throttle::reset(&_env, &account_key).await.map_err(reject::custom)?;
+    println!("[mfa_login_handler] Authenticated user '{}' with second factor", &user.email);
+    let body = start_session(user, addr, &headers, &_env).await.map_err(reject::custom)?;
+    Ok(cookies::session_reply(&_env, body))
+}
+
+// Starts two-factor enrolment. The secret is not active until confirmed with mfa_confirm_handler.
+pub async fn mfa_enroll_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    if let Some(mfa) = auth::service::get_mfa(&_env, &_user.id).await.map_err(reject::custom)? {
+        if mfa.enabled_at.is_some() {
+            return Err(reject::custom(AppError::MfaAlreadyEnabledError));
+        }
+    }
+    let user = users::service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+    let secret = totp::generate_secret();
+    auth::service::set_mfa_pending_secret(&_env, &_user.id, &secret).await.map_err(reject::custom)?;
+    println!("[mfa_enroll_handler] Two-factor enrolment started for user {}", _user);
+    let otpauth_uri = totp::otpauth_uri(&_env.config().mfa_issuer, &user.email, &secret);
+    Ok(warp::reply::json(&MfaEnrollResponse { secret, otpauth_uri }))
+}
+
+// Activates two-factor authentication with a first code and returns the one-time recovery codes
+pub async fn mfa_confirm_handler(_req: MfaCodeRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let mfa = auth::service::get_mfa(&_env, &_user.id).await.map_err(reject::custom)?;
+    let secret = match mfa {
+        Some(Mfa { enabled_at: Some(_), .. }) => return Err(reject::custom(AppError::MfaAlreadyEnabledError)),
+        Some(Mfa { pending_secret: Some(secret), .. }) => secret,
+        _ => return Err(reject::custom(AppError::MfaCodeError)),
+    };
+    let step = totp::verify(&secret, &_req.code, Utc::now().timestamp()).ok_or(reject::custom(AppError::MfaCodeError))?;
+
+    let recovery_codes: Vec<String> = (0..MFA_RECOVERY_CODES).map(|_| auth::utils::generate_recovery_code()).collect();
+    let hashes = recovery_codes.iter().map(|code| auth::utils::hash_recovery_code(code)).collect();
+    auth::service::enable_mfa(&_env, &_user.id, &secret, step, hashes).await.map_err(reject::custom)?;
+    println!("[mfa_confirm_handler] Two-factor authentication enabled for user {}", _user);
+    Ok(warp::reply::json(&MfaRecoveryCodesResponse { recovery_codes }))
+}
+
+// Turns two-factor authentication off, which requires a valid code
+pub async fn mfa_disable_handler(_req: MfaCodeRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let mfa = match auth::service::get_mfa(&_env, &_user.id).await.map_err(reject::custom)? {
+        Some(mfa) if mfa.enabled_at.is_some() => mfa,
+        _ => return Err(reject::custom(AppError::MfaCodeError)),
+    };
+    if !check_mfa_code(&mfa, &_req.code, &_env).await.map_err(reject::custom)? {
+        return Err(reject::custom(AppError::MfaCodeError));
+    }
+    auth::service::disable_mfa(&_user.id, _env.db()).await.map_err(reject::custom)?;
+    println!("[mfa_disable_handler] Two-factor authentication disabled for user {}", _user);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Two-factor authentication disabled"})))
+}
+
+// Accepts a TOTP code for a step that was not used yet, or consumes a recovery code
+async fn check_mfa_code(mfa: &Mfa, code: &str, _env: &Environment) -> Result<bool> {
+    if let Some(step) = mfa.secret.as_ref().and_then(|secret| totp::verify(secret, code, Utc::now().timestamp())) {
+        return auth::service::use_mfa_step(&mfa.user_id, step, _env.db()).await;
+    }
+    auth::service::use_recovery_code(&mfa.user_id, &auth::utils::hash_recovery_code(code), _env.db()).await
+}
+
+// Starts single sign-on by redirecting to the identity provider, with PKCE and a single-use state
+pub async fn oidc_login_handler(_env: Environment) -> WebResult<impl Reply> {
+    let provider = oidc::Provider::from_env(&_env).map_err(reject::custom)?;
+    let metadata = oidc::discover(&_env, &provider).await.map_err(reject::custom)?;
+
+    let state = generate_token();
+    let oidc_state = OidcState { nonce: generate_token(), code_verifier: generate_token() };
+    let expires_at = Utc::now() + chrono::Duration::seconds(OIDC_STATE_TTL);
+    auth::service::create_oidc_state(&state, &oidc_state, expires_at, _env.db()).await.map_err(reject::custom)?;
+
+    let url = oidc::authorization_url(&metadata, &provider, &state, &oidc_state.nonce, &oidc_state.code_verifier).map_err(reject::custom)?;
+    let uri: Uri = url.parse().map_err(|_| reject::custom(AppError::OidcError))?;
+    Ok(cookies::with_oidc_state(&_env, warp::redirect::temporary(uri), &state))
+}
+
+// Completes single sign-on: redeems the code, validates the ID token and logs in the linked or provisioned user
+pub async fn oidc_callback_handler(_query: OidcCallbackQuery, _env: Environment, addr: Option<SocketAddr>, headers: HeaderMap<HeaderValue>) -> WebResult<impl Reply> {
+    let provider = oidc::Provider::from_env(&_env).map_err(reject::custom)?;
+    cookies::check_oidc_state(&headers, &_query.state).map_err(|e| {
+        println!("[oidc_callback_handler] State does not match the browser's");
+        reject::custom(e)
+    })?;
+    let oidc_state = auth::service::consume_oidc_state(&_query.state, _env.db()).await.map_err(reject::custom)?;
+    let code = match (&_query.code, &_query.error) {
+        (Some(code), None) => code,
+        _ => {
+            println!("[oidc_callback_handler] Provider returned error {:?}", &_query.error);
+            return Err(reject::custom(AppError::OidcError));
+        },
+    };
+
+    let metadata = oidc::discover(&_env, &provider).await.map_err(reject::custom)?;
+    let id_token = oidc::exchange_code(&_env, &metadata, &provider, code, &oidc_state.code_verifier).await.map_err(reject::custom)?;
+    let claims = oidc::validate_id_token(&_env, &metadata, &provider, &id_token, &oidc_state.nonce).await.map_err(reject::custom)?;
+    let user = oidc_user(&metadata, &claims, &_env).await.map_err(reject::custom)?;
+
+    let role = &user.role.clone().unwrap();
+    let user_id = user.id.clone().unwrap();
+    let mfa = auth::service::get_mfa(&_env, &user_id).await.map_err(reject::custom)?;
+    if matches!(mfa, Some(m) if m.enabled_at.is_some()) {
+        println!("[oidc_callback_handler] User '{}' signed in through single sign-on, awaiting second factor", &user.email);
+        let mfa_token = create_mfa_token(&_env, &user_id, role).map_err(reject::custom)?;
+        return Ok(cookies::clear_oidc_state(&_env, warp::reply::json(&MfaChallenge { mfa_required: true, mfa_token })));
+    }
+
+    println!("[oidc_callback_handler] Authenticated user '{}' ({}) through single sign-on", &user.email, &role);
+    let body = start_session(user, addr, &headers, &_env).await.map_err(reject::custom)?;
+    Ok(cookies::clear_oidc_state(&_env, cookies::session_reply(&_env, body)))
+}
+
+// Finds the user linked to the provider account. Otherwise links the user with the same, provider verified,
+// email address or provisions a new user without a password, unless registration is by invitation only.
+// Users whose role grants any permission are not linked by email, as that would hand their rights to whoever
+// controls the address at the provider.
+async fn oidc_user(metadata: &oidc::Metadata, claims: &oidc::IdTokenClaims, _env: &Environment) -> Result<User> {
+    let subject = format!("{}|{}", metadata.issuer, claims.sub);
+    match users::service::get_user_by_oidc_subject(&subject, _env.db()).await {
+        Ok(user) => return Ok(user),
+        Err(AppError::UserNotFound) => (),
+        Err(e) => return Err(e),
+    }
+
+    let email = match &claims.email {
+        Some(email) if claims.email_verified => email,
+        _ => {
+            println!("[oidc_user] No verified email for subject {}", &subject);
+            return Err(AppError::OidcError);
+        },
+    };
+    let user_id = match users::service::get_user_by_email(email, _env.db()).await {
+        Ok(user) => {
+            let permissions = roles::service::permissions_for(_env, &user.role.clone().unwrap_or(Role::User)).await?;
+            if !permissions.is_empty() {
+                println!("[oidc_user] Refused to link privileged user {} to {}", email, &subject);
+                return Err(AppError::OidcError);
+            }
+            println!("[oidc_user] Linking user {} to {}", email, &subject);
+            let user_id = user.id.unwrap();
+            if user.verified_at.is_none() {
+                users::service::set_user_verified(&user_id, _env.db()).await?;
+            }
+            user_id
+        },
+        Err(AppError::UserNotFound) if _env.config().invite_only_registration => {
+            println!("[oidc_user] Registration without invitation refused for {}", email);
+            return Err(AppError::InvitationRequiredError);
+        },
+        Err(AppError::UserNotFound) => {
+            println!("[oidc_user] Provisioning user {} for {}", email, &subject);
+            let user = User {
+                id: None,
+                email: email.to_owned(),
+                name: claims.name.clone().unwrap_or_else(|| email.to_owned()),
+                password: None,
+                role: Some(Role::User),
+                created_at: Some(Utc::now()),
+                updated_at: Some(Utc::now()),
+                verified_at: Some(Utc::now()),
+                disabled_at: None,
+                preferences: None,
+                avatar_url: None,
+            };
+            users::service::create_user(user, _env.db()).await?
+        },
+        Err(e) => return Err(e),
+    };
+    users::service::set_user_oidc_subject(&user_id, &subject, _env.db()).await?;
+    users::service::get_user_by_id(user_id, _env.db()).await
+}
+
+// Publishes the public signing keys so that other services can verify our tokens
+pub async fn jwks_handler(_env: Environment) -> WebResult<impl Reply> {
+    Ok(warp::reply::json(&_env.jwt_keys().jwks()))
+}
+
+// Starts a new session (refresh token family) for the user, recording the device it was started from
+async fn start_session(user: User, addr: Option<SocketAddr>, headers: &HeaderMap<HeaderValue>, _env: &Environment) -> Result<LoginResponse> {
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + chrono::Duration::seconds(_env.config().refresh_token_ttl);
+    let user_agent = sessions::utils::user_agent(headers);
+    let ip = throttle::client_ip(addr, headers, _env);
+    sessions::service::create_session(&session_id, user.id.as_ref().unwrap(), user_agent, ip, expires_at, _env.db()).await?;
+    issue_tokens(user, &session_id, _env).await
+}
+
+async fn issue_tokens(user: User, family_id: &str, _env: &Environment) -> Result<LoginResponse> {
+    if user.disabled_at.is_some() {
+        return Err(AppError::AccountDisabledError);
+    }
+    let user_id = user.id.clone().unwrap();
+    let role = user.role.clone().unwrap();
+    let access_token = create_jwt(_env, &user_id, &role, family_id)?;
+    let expires_at = Utc::now() + chrono::Duration::seconds(_env.config().refresh_token_ttl);
+    let refresh_token = auth::service::create_refresh_token(&user_id, family_id, expires_at, _env.db()).await?;
+    Ok(LoginResponse::from_user(user, access_token, refresh_token))
 }
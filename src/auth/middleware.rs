@@ -1,74 +1,149 @@
 use warp::Filter;
-use warp::http::{HeaderMap, HeaderValue};
+use warp::filters::path::FullPath;
+use warp::http::{HeaderMap, HeaderValue, Method};
 
-use crate::{Result, WebResult};
-use crate::auth::{BEARER, JWT_SECRET};
+use crate::{api_keys, audit, roles, users, Result, WebResult};
+use crate::auth::{ACCESS_TOKEN_COOKIE, API_KEY, BEARER, cookies, decode_jwt, revocation};
 use crate::auth::models::{AuthUser, Claims, Role};
+use crate::environment::{self, Environment};
 use crate::error::AppError;
 
+// Credentials accepted in the Authorization header, or the access token cookie in cookie session mode
+enum Credentials {
+    Jwt(String),
+    ApiKey(String),
+    Cookie(String),
+}
+
 // Authentication middleware
-pub fn authenticated() -> impl Filter<Extract=(AuthUser, ), Error=warp::reject::Rejection> + Clone {
-    warp::header::headers_cloned()
-        .map(move |headers: HeaderMap<HeaderValue>| headers)
+pub fn authenticated(_env: Environment) -> impl Filter<Extract=(AuthUser, ), Error=warp::reject::Rejection> + Clone {
+    environment::with_env(_env)
+        .and(warp::header::headers_cloned())
+        .and(warp::method())
+        .and(warp::path::full())
         .and_then(authorize_any)
 }
 
-// Decodes JWT from header, checks its validity and assembles User object to be passed to the handlers
-async fn authorize_any(headers: HeaderMap<HeaderValue>) -> WebResult<AuthUser> {
-    match jwt_from_header(&headers) {
-        Ok(jwt) => {
-            let decoded = jsonwebtoken::decode::<Claims>(
-                &jwt,
-                &jsonwebtoken::DecodingKey::from_secret(JWT_SECRET),
-                &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS512),
-            )
-                .map_err(|_| warp::reject::custom(AppError::JWTTokenError))?;
+// Like authenticated, but only accepts access tokens from the user's own interactive login, not API keys or impersonation
+pub fn with_session(_env: Environment) -> impl Filter<Extract=(AuthUser, ), Error=warp::reject::Rejection> + Clone {
+    authenticated(_env).and_then(|user: AuthUser| async move {
+        if user.api_key_id.is_some() || user.actor.is_some() {
+            return Err(warp::reject::custom(AppError::NoPermissionError));
+        }
+        Ok(user)
+    })
+}
 
-            let user = AuthUser::new(decoded.claims.sub, decoded.claims.role);
-            Ok(user)
+// Like authenticated, but refuses the access token cookie. For the routes that change data on GET, which the CSRF
+// check of cookie sessions does not cover.
+pub fn with_authorization_header(_env: Environment) -> impl Filter<Extract=(AuthUser, ), Error=warp::reject::Rejection> + Clone {
+    warp::header::headers_cloned().and(authenticated(_env)).and_then(|headers: HeaderMap<HeaderValue>, user: AuthUser| async move {
+        if headers.get(warp::http::header::AUTHORIZATION).is_none() {
+            return Err(warp::reject::custom(AppError::NoAuthHeaderError));
         }
-        Err(e) => return Err(warp::reject::custom(AppError::from(e))),
-    }
+        Ok(user)
+    })
 }
 
-// with_auth and authorize handles authorization of specific roles
-pub fn with_auth(role: Role) -> impl Filter<Extract=(AuthUser, ), Error=warp::reject::Rejection> + Clone {
-    warp::header::headers_cloned()
-        .map(move |headers: HeaderMap<HeaderValue>| (role.clone(), headers))
-        .and_then(authorize)
+// Checks the credentials from the header and assembles User object to be passed to the handlers.
+// Every request made while impersonating a user is recorded in the audit log.
+async fn authorize_any(_env: Environment, headers: HeaderMap<HeaderValue>, method: Method, path: FullPath) -> WebResult<AuthUser> {
+    let user = authenticate(&_env, &headers, &method).await?;
+    if let Some(actor) = &user.actor {
+        let details = format!("{} {}", method, path.as_str());
+        audit::service::record(&_env, audit::IMPERSONATION_REQUEST, Some(actor), Some(&user.id), Some(details)).await.map_err(warp::reject::custom)?;
+    }
+    Ok(user)
 }
 
-async fn authorize((role, headers): (Role, HeaderMap<HeaderValue>)) -> WebResult<AuthUser> {
-    match jwt_from_header(&headers) {
-        Ok(jwt) => {
-            let decoded = jsonwebtoken::decode::<Claims>(
-                &jwt,
-                &jsonwebtoken::DecodingKey::from_secret(JWT_SECRET),
-                &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS512),
-            )
-                .map_err(|_| warp::reject::custom(AppError::JWTTokenError))?;
+// with_permission only lets through users whose role (and API key, if any) grants the permission
+pub fn with_permission(_env: Environment, permission: &'static str) -> impl Filter<Extract=(AuthUser, ), Error=warp::reject::Rejection> + Clone {
+    authenticated(_env).and_then(move |user: AuthUser| async move {
+        if !user.has_permission(permission) {
+            println!("[with_permission] User {} ({}) lacks permission {}", &user.id, &user.role, permission);
+            return Err(warp::reject::custom(AppError::NoPermissionError));
+        }
+        Ok(user)
+    })
+}
 
-            if role == Role::Admin && Role::from_str(&decoded.claims.role) != Role::Admin {
-                return Err(warp::reject::custom(AppError::NoPermissionError));
-            }
-            let user = AuthUser::new(decoded.claims.sub, decoded.claims.role);
-            Ok(user)
+async fn authenticate(_env: &Environment, headers: &HeaderMap<HeaderValue>, method: &Method) -> WebResult<AuthUser> {
+    match jwt_from_header(headers).map_err(warp::reject::custom)? {
+        Credentials::Jwt(jwt) => jwt_user(_env, &jwt).await,
+        Credentials::ApiKey(key) => api_key_user(_env, &key).await.map_err(warp::reject::custom),
+        Credentials::Cookie(jwt) if _env.config().cookie_sessions => {
+            cookies::check_csrf(headers, method).map_err(warp::reject::custom)?;
+            jwt_user(_env, &jwt).await
         }
-        Err(e) => return Err(warp::reject::custom(AppError::from(e))),
+        Credentials::Cookie(_) => Err(warp::reject::custom(AppError::NoAuthHeaderError)),
+    }
+}
+
+async fn jwt_user(_env: &Environment, jwt: &str) -> WebResult<AuthUser> {
+    let claims = decode_jwt(_env, jwt).map_err(warp::reject::custom)?;
+    check_claims(_env, &claims).await?;
+    // Tokens of deleted or disabled users stop working, even where their revocation was missed
+    let owner = users::service::get_user_by_id(claims.sub.clone(), _env.db()).await.map_err(|_e| {
+        println!("[jwt_user] User {} of token not found", &claims.sub);
+        warp::reject::custom(AppError::JWTTokenError)
+    })?;
+    if owner.disabled_at.is_some() {
+        return Err(warp::reject::custom(AppError::AccountDisabledError));
+    }
+    // The role is the current one of the user rather than the one in the token, so that a demotion takes effect at once
+    let mut user = AuthUser::from_claims(claims);
+    user.role = owner.role.unwrap_or(Role::User);
+    user.permissions = roles::service::permissions_for(_env, &user.role).await.map_err(warp::reject::custom)?;
+    Ok(user)
+}
+
+// Rejects single-purpose tokens (which are not access tokens) and revoked tokens
+async fn check_claims(_env: &Environment, claims: &Claims) -> WebResult<()> {
+    if claims.purpose.is_some() {
+        return Err(warp::reject::custom(AppError::JWTTokenError));
+    }
+    match revocation::is_revoked(_env, claims).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(warp::reject::custom(AppError::JWTTokenError)),
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
-fn jwt_from_header(headers: &HeaderMap<HeaderValue>) -> Result<String> {
+// API keys act on behalf of their owner, limited to the owner's current permissions that the key's scopes allow
+async fn api_key_user(_env: &Environment, key: &str) -> Result<AuthUser> {
+    let api_key = match api_keys::service::use_api_key(key, _env.db()).await? {
+        Some(api_key) => api_key,
+        None => return Err(AppError::InvalidApiKeyError),
+    };
+    let owner = users::service::get_user_by_id(api_key.user_id.clone(), _env.db()).await.map_err(|_e| {
+        println!("[api_key_user] Owner of API key {} not found", &api_key.id);
+        return AppError::InvalidApiKeyError;
+    })?;
+    if owner.disabled_at.is_some() {
+        return Err(AppError::AccountDisabledError);
+    }
+    let role = owner.role.unwrap_or(Role::User);
+    let owner_permissions = roles::service::permissions_for(_env, &role).await?;
+    let permissions = api_keys::utils::effective_permissions(&api_key, &owner_permissions);
+    Ok(AuthUser::from_api_key(&api_key, role, permissions))
+}
+
+fn jwt_from_header(headers: &HeaderMap<HeaderValue>) -> Result<Credentials> {
     let header = match headers.get(warp::http::header::AUTHORIZATION) {
         Some(v) => v,
-        None => return Err(AppError::NoAuthHeaderError),
+        None => return cookies::cookie_value(headers, ACCESS_TOKEN_COOKIE)
+            .map(Credentials::Cookie)
+            .ok_or(AppError::NoAuthHeaderError),
     };
     let auth_header = match std::str::from_utf8(header.as_bytes()) {
         Ok(v) => v,
         Err(_) => return Err(AppError::NoAuthHeaderError),
     };
-    if !auth_header.starts_with(BEARER) {
-        return Err(AppError::InvalidAuthHeaderError);
+    if auth_header.starts_with(BEARER) {
+        return Ok(Credentials::Jwt(auth_header.trim_start_matches(BEARER).to_owned()));
+    }
+    if auth_header.starts_with(API_KEY) {
+        return Ok(Credentials::ApiKey(auth_header.trim_start_matches(API_KEY).to_owned()));
     }
-    Ok(auth_header.trim_start_matches(BEARER).to_owned())
+    Err(AppError::InvalidAuthHeaderError)
 }
@@ -1,18 +1,42 @@
-use crate::auth::models::{Claims, Role};
+use crate::auth::models::{Actor, Claims, Role};
+use crate::environment::Environment;
+use crate::environment::mailer::Message;
 use crate::error::AppError;
 use crate::Result;
 
+pub mod cookies;
 pub mod handlers;
 pub mod middleware;
 pub mod models;
+pub mod oidc;
+pub mod revocation;
 pub mod routes;
+pub mod service;
+pub mod throttle;
+pub mod totp;
+pub mod utils;
 
 const BEARER: &str = "Bearer ";
-const JWT_SECRET: &[u8] = b"secret";
+const API_KEY: &str = "ApiKey ";
+const PASSWORD_RESET: &str = "password_reset";
+const EMAIL_VERIFICATION: &str = "email_verification";
+const MFA_PURPOSE: &str = "mfa";
+const MFA_TOKEN_TTL: i64 = 300;
+const MFA_RECOVERY_CODES: usize = 10;
+const OIDC_STATE_TTL: i64 = 600;
+// Cookie session mode, see environment COOKIE_SESSIONS
+const ACCESS_TOKEN_COOKIE: &str = "access_token";
+const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+const CSRF_COOKIE: &str = "csrf_token";
+const CSRF_HEADER: &str = "x-csrf-token";
+// Ties the single sign-on state to the browser, see cookies::with_oidc_state
+const OIDC_STATE_COOKIE: &str = "oidc_state";
 
-pub fn create_jwt(uid: &str, role: &Role) -> Result<String> {
-    let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::seconds(259200))
+// Issues an access token for the session (refresh token family) `sid`
+pub fn create_jwt(_env: &Environment, uid: &str, role: &Role, sid: &str) -> Result<String> {
+    let now = chrono::Utc::now();
+    let expiration = now
+        .checked_add_signed(chrono::Duration::seconds(_env.config().access_token_ttl))
         .expect("valid timestamp")
         .timestamp();
 
@@ -20,8 +44,100 @@ pub fn create_jwt(uid: &str, role: &Role) -> Result<String> {
         sub: uid.to_owned(),
         role: role.to_string(),
         exp: expiration as usize,
+        iat: now.timestamp() as usize,
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: sid.to_owned(),
+        iat_ms: Some(now.timestamp_millis() as usize),
+        purpose: None,
+        act: None,
     };
-    let header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS512);
-    jsonwebtoken::encode(&header, &claims, &jsonwebtoken::EncodingKey::from_secret(JWT_SECRET))
+    encode_jwt(_env, &claims)
+}
+
+// Issues a token for `uid` on behalf of the admin `actor_id`. It cannot be refreshed and lasts IMPERSONATION_TTL.
+pub fn create_impersonation_jwt(_env: &Environment, uid: &str, role: &Role, actor_id: &str, expires_at: chrono::DateTime<chrono::Utc>) -> Result<String> {
+    let now = chrono::Utc::now();
+    let claims = Claims {
+        sub: uid.to_owned(),
+        role: role.to_string(),
+        exp: expires_at.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: uuid::Uuid::new_v4().to_string(),
+        iat_ms: Some(now.timestamp_millis() as usize),
+        purpose: None,
+        act: Some(Actor { sub: actor_id.to_owned() }),
+    };
+    encode_jwt(_env, &claims)
+}
+
+// Issues the short-lived token that proves the password was checked, to be exchanged with a second factor
+pub fn create_mfa_token(_env: &Environment, uid: &str, role: &Role) -> Result<String> {
+    let now = chrono::Utc::now();
+    let claims = Claims {
+        sub: uid.to_owned(),
+        role: role.to_string(),
+        exp: (now + chrono::Duration::seconds(MFA_TOKEN_TTL)).timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: String::new(),
+        iat_ms: Some(now.timestamp_millis() as usize),
+        purpose: Some(MFA_PURPOSE.to_owned()),
+        act: None,
+    };
+    encode_jwt(_env, &claims)
+}
+
+fn encode_jwt(_env: &Environment, claims: &Claims) -> Result<String> {
+    let keys = _env.jwt_keys();
+    let mut header = jsonwebtoken::Header::new(keys.algorithm());
+    header.kid = Some(keys.active_kid().to_owned());
+    jsonwebtoken::encode(&header, claims, keys.encoding_key())
         .map_err(|_| AppError::JWTTokenCreationError)
 }
+
+// Verifies the token with the key referenced by its `kid` header, so tokens signed with a retired key keep working.
+// The algorithm comes from the key rather than the token header, so an HMAC token can never pass as an RSA one.
+pub fn decode_jwt(_env: &Environment, jwt: &str) -> Result<Claims> {
+    let header = jsonwebtoken::decode_header(jwt).map_err(|_| AppError::JWTTokenError)?;
+    let (algorithm, key) = _env
+        .jwt_keys()
+        .decoding_key(header.kid.as_deref())
+        .ok_or(AppError::JWTTokenError)?;
+    let decoded = jsonwebtoken::decode::<Claims>(
+        jwt,
+        key,
+        &jsonwebtoken::Validation::new(algorithm),
+    )
+        .map_err(|_| AppError::JWTTokenError)?;
+    Ok(decoded.claims)
+}
+
+
+// Emails a link to choose a new password, valid for PASSWORD_RESET_TTL
+pub async fn send_password_reset_email(_env: &Environment, user_id: &str, email: &str, name: &str) -> Result<()> {
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(_env.config().password_reset_ttl);
+    let token = service::create_user_token(user_id, PASSWORD_RESET, expires_at, _env.db()).await?;
+    _env.mailer().send(Message {
+        to: email.to_owned(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hello {},\n\nUse the link below to choose a new password. It expires in {} minutes.\n\n{}/reset-password?token={}\n\nIf you did not ask to reset your password, you can ignore this message.\n",
+            name, _env.config().password_reset_ttl / 60, _env.config().app_url.trim_end_matches('/'), token),
+    });
+    Ok(())
+}
+
+// Emails a link that confirms the user owns the address
+pub async fn send_verification_email(_env: &Environment, user_id: &str, email: &str, name: &str) -> Result<()> {
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(_env.config().email_verification_ttl);
+    let token = service::create_user_token(user_id, EMAIL_VERIFICATION, expires_at, _env.db()).await?;
+    _env.mailer().send(Message {
+        to: email.to_owned(),
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Hello {},\n\nPlease confirm your email address by opening the link below. It expires in {} hours.\n\n{}/verify-email?token={}\n",
+            name, _env.config().email_verification_ttl / 3600, _env.config().app_url.trim_end_matches('/'), token),
+    });
+    Ok(())
+}
@@ -1,5 +1,6 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::api_keys::models::ApiKey;
 use crate::users::models::User;
 use chrono::{DateTime, Utc};
 
@@ -9,16 +10,54 @@ pub struct AuthUser {
     pub id: String,
     pub role: Role,
     pub login_at: DateTime<Utc>,
+    pub token_id: String,
+    pub session_id: String,
+    // Milliseconds
+    pub issued_at: usize,
+    pub expires_at: usize,
+    // Set when the request was authenticated with a personal API key instead of an access token
+    pub api_key_id: Option<String>,
+    // Resolved from the role definitions (and API key scopes) by the middleware
+    pub permissions: Vec<String>,
+    // Admin acting as this user, for impersonated sessions
+    pub actor: Option<String>,
 }
 
 impl AuthUser {
-    pub fn new(id: String, role: String) -> AuthUser {
+    pub fn from_claims(claims: Claims) -> AuthUser {
+        let issued_at = claims.issued_at_millis();
         AuthUser {
-            id: id,
-            role: Role::from_str(&role),
+            id: claims.sub,
+            role: Role::from_str(&claims.role),
             login_at: Utc::now(),
+            token_id: claims.jti,
+            session_id: claims.sid,
+            issued_at,
+            expires_at: claims.exp,
+            api_key_id: None,
+            permissions: Vec::new(),
+            actor: claims.act.map(|act| act.sub),
         }
     }
+
+    pub fn from_api_key(api_key: &ApiKey, role: Role, permissions: Vec<String>) -> AuthUser {
+        AuthUser {
+            id: api_key.user_id.clone(),
+            role,
+            login_at: Utc::now(),
+            token_id: api_key.id.clone(),
+            session_id: String::new(),
+            issued_at: api_key.created_at.timestamp_millis() as usize,
+            expires_at: api_key.expires_at.map_or(0, |expires_at| expires_at.timestamp() as usize),
+            api_key_id: Some(api_key.id.clone()),
+            permissions,
+            actor: None,
+        }
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
 }
 
 impl std::fmt::Display for AuthUser {
@@ -29,6 +68,14 @@ impl std::fmt::Display for AuthUser {
     }
 }
 
+// Registration body: the new user, and the invitation code when registering by invitation
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    #[serde(flatten)]
+    pub user: User,
+    pub invitation_code: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct LoginRequest {
     pub email: String,
@@ -41,39 +88,184 @@ pub struct LoginResponse {
     pub email: String,
     pub name: String,
     pub roles: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    // Empty, and left out, when the tokens are sent as cookies
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub access_token: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub refresh_token: String,
 }
 
 impl LoginResponse {
-    pub fn from_user(user: User, access_token: String) -> LoginResponse {
+    pub fn from_user(user: User, access_token: String, refresh_token: String) -> LoginResponse {
         return LoginResponse {
             id: user.id.unwrap(),
             email: user.email,
             name: user.name,
             roles: vec!(user.role.unwrap().to_string()),
+            avatar_url: user.avatar_url,
             access_token,
+            refresh_token,
         };
     }
 }
 
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    // Taken from the refresh token cookie when empty
+    #[serde(default)]
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+// Returned by login instead of a LoginResponse when the account has two-factor authentication enabled
+#[derive(Serialize)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    // Either a TOTP code or one of the recovery codes
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct MfaEnrollResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct MfaRecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+// Two-factor settings of a user. The secret only becomes active once enrolment is confirmed with a first code.
+#[derive(Clone, Debug)]
+pub struct Mfa {
+    pub user_id: String,
+    pub secret: Option<String>,
+    pub pending_secret: Option<String>,
+    pub enabled_at: Option<DateTime<Utc>>,
+    // Last accepted TOTP time step, so that a code cannot be replayed
+    pub last_step: i64,
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+// Pending single sign-on, kept between the redirect to the provider and its callback
+#[derive(Clone, Debug)]
+pub struct OidcState {
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+// Stored refresh token. Every login starts a new family and each refresh rotates the token within it.
+#[derive(Clone, Debug)]
+pub struct RefreshToken {
+    pub id: String,
+    pub family_id: String,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Claims {
     pub sub: String,
     pub role: String,
     pub exp: usize,
+    // Tokens issued before revocation support lack these, and can only be revoked together with all of the user's tokens
+    #[serde(default)]
+    pub iat: usize,
+    #[serde(default)]
+    pub jti: String,
+    #[serde(default)]
+    pub sid: String,
+    // Issue time in milliseconds, as `iat` is too coarse to tell tokens issued within the second of a revocation apart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_ms: Option<usize>,
+    // Set on tokens that are only valid for a single step, such as completing a two-factor login
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purpose: Option<String>,
+    // Actor claim (RFC 8693) of impersonation tokens, identifying the admin acting as `sub`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+impl Claims {
+    // Tokens issued before revocation support have no issue time, and count as issued at the epoch
+    pub fn issued_at_millis(&self) -> usize {
+        self.iat_ms.unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Actor {
+    pub sub: String,
+}
+
+#[derive(Serialize)]
+pub struct ImpersonationResponse {
+    pub id: String,
+    pub email: String,
+    pub name: String,
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Admin, Editor, Author and User are built in, any other role is defined in the roles collection. Stored as its name.
+#[derive(Clone, PartialEq, Debug)]
 pub enum Role {
     User,
     Admin,
+    Editor,
+    Author,
+    Custom(String),
 }
 
 impl Role {
     pub fn from_str(role: &str) -> Role {
         match role {
             "Admin" => Role::Admin,
-            _ => Role::User,
+            "Editor" => Role::Editor,
+            "Author" => Role::Author,
+            "User" | "" => Role::User,
+            other => Role::Custom(other.to_owned()),
         }
     }
 }
@@ -83,6 +275,21 @@ impl std::fmt::Display for Role {
         match self {
             Role::User => write!(f, "User"),
             Role::Admin => write!(f, "Admin"),
+            Role::Editor => write!(f, "Editor"),
+            Role::Author => write!(f, "Author"),
+            Role::Custom(name) => write!(f, "{}", name),
         }
     }
 }
+
+impl Serialize for Role {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Role {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Role, D::Error> {
+        Ok(Role::from_str(&String::deserialize(deserializer)?))
+    }
+}
@@ -0,0 +1,176 @@
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+use crate::environment::Environment;
+use crate::error::AppError;
+
+// Identity provider settings, taken from the OIDC_* configuration
+#[derive(Clone, Debug)]
+pub struct Provider {
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+}
+
+impl Provider {
+    pub fn from_env(_env: &Environment) -> Result<Provider> {
+        let config = _env.config();
+        let (issuer_url, client_id) = match (&config.oidc_issuer_url, &config.oidc_client_id) {
+            (Some(issuer_url), Some(client_id)) => (issuer_url, client_id),
+            _ => return Err(AppError::OidcNotConfiguredError),
+        };
+        let redirect_url = config.oidc_redirect_url.clone()
+            .unwrap_or_else(|| format!("{}/api/auth/oidc/callback", config.app_url.trim_end_matches('/')));
+        Ok(Provider {
+            issuer_url: issuer_url.trim_end_matches('/').to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: config.oidc_client_secret.clone(),
+            redirect_url,
+            scopes: config.oidc_scopes.clone(),
+        })
+    }
+}
+
+// Subset of the provider metadata published at /.well-known/openid-configuration
+#[derive(Clone, Debug, Deserialize)]
+pub struct Metadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+
+pub async fn discover(_env: &Environment, provider: &Provider) -> Result<Metadata> {
+    let url = format!("{}/.well-known/openid-configuration", provider.issuer_url);
+    let metadata: Metadata = get_json(_env, &url).await?;
+    // The issuer must match the configured one exactly, as required by OpenID Connect Discovery
+    if metadata.issuer.trim_end_matches('/') != provider.issuer_url {
+        println!("ERROR [oidc::discover] Issuer mismatch: expected {}, got {}", &provider.issuer_url, &metadata.issuer);
+        return Err(AppError::OidcError);
+    }
+    Ok(metadata)
+}
+
+
+pub fn authorization_url(metadata: &Metadata, provider: &Provider, state: &str, nonce: &str, code_verifier: &str) -> Result<String> {
+    let mut url = url::Url::parse(&metadata.authorization_endpoint).map_err(|_e| {
+        println!("ERROR [oidc::authorization_url] {:?}", _e);
+        return AppError::OidcError;
+    })?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &provider.redirect_url)
+        .append_pair("scope", &provider.scopes)
+        .append_pair("state", state)
+        .append_pair("nonce", nonce)
+        .append_pair("code_challenge", &code_challenge(code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    Ok(url.into())
+}
+
+
+// Redeems the authorization code and returns the ID token
+pub async fn exchange_code(_env: &Environment, metadata: &Metadata, provider: &Provider, code: &str, code_verifier: &str) -> Result<String> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &provider.redirect_url),
+        ("client_id", &provider.client_id),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(client_secret) = &provider.client_secret {
+        form.push(("client_secret", client_secret));
+    }
+    let response = _env.http().post(&metadata.token_endpoint).form(&form).send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_e| {
+            println!("ERROR [oidc::exchange_code] {:?}", _e);
+            return AppError::OidcError;
+        })?;
+    let token: TokenResponse = response.json().await.map_err(|_e| {
+        println!("ERROR [oidc::exchange_code] {:?}", _e);
+        return AppError::OidcError;
+    })?;
+    Ok(token.id_token)
+}
+
+
+// Checks the ID token signature against the provider keys, its issuer, audience, expiry and nonce
+pub async fn validate_id_token(_env: &Environment, metadata: &Metadata, provider: &Provider, id_token: &str, nonce: &str) -> Result<IdTokenClaims> {
+    let header = decode_header(id_token).map_err(|_| AppError::OidcError)?;
+    // Symmetric algorithms would verify with the client secret, which the provider keys cannot vouch for
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+        println!("ERROR [oidc::validate_id_token] Unsupported algorithm {:?}", header.alg);
+        return Err(AppError::OidcError);
+    }
+
+    let jwks: JwkSet = get_json(_env, &metadata.jwks_uri).await?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+    let key = match jwk {
+        Some(jwk) if !matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) => DecodingKey::from_jwk(jwk).map_err(|_| AppError::OidcError)?,
+        _ => {
+            println!("ERROR [oidc::validate_id_token] No provider key for kid {:?}", &header.kid);
+            return Err(AppError::OidcError);
+        },
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_audience(&[&provider.client_id]);
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation).map_err(|_e| {
+        println!("ERROR [oidc::validate_id_token] {:?}", _e);
+        return AppError::OidcError;
+    })?.claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        println!("ERROR [oidc::validate_id_token] Nonce mismatch");
+        return Err(AppError::OidcError);
+    }
+    Ok(claims)
+}
+
+
+// PKCE S256 challenge for the verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(Sha256::digest(code_verifier.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
+
+
+async fn get_json<T: serde::de::DeserializeOwned>(_env: &Environment, url: &str) -> Result<T> {
+    let response = _env.http().get(url).send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_e| {
+            println!("ERROR [oidc::get_json] {} {:?}", url, _e);
+            return AppError::OidcError;
+        })?;
+    response.json().await.map_err(|_e| {
+        println!("ERROR [oidc::get_json] {} {:?}", url, _e);
+        return AppError::OidcError;
+    })
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{TimeZone, Utc};
+use mongodb::bson::doc;
+
+use crate::Result;
+use crate::auth::models::{AuthUser, Claims};
+use crate::environment::Environment;
+use crate::error::AppError;
+
+// Verdicts are cached for a short time only, so revocations made by other instances are picked up quickly
+const CACHE_TTL: Duration = Duration::from_secs(30);
+const CACHE_MAX_SIZE: usize = 10_000;
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    user_id: String,
+    session_id: String,
+    // Milliseconds
+    issued_at: usize,
+    expires_at: usize,
+    revoked: bool,
+    checked_at: Instant,
+}
+
+// In-process cache of revocation lookups keyed by token id, shared by all clones of the environment.
+// It holds at most CACHE_MAX_SIZE entries; evicted tokens are looked up in the database again.
+#[derive(Clone, Debug, Default)]
+pub struct RevocationCache {
+    entries: Arc<RwLock<HashMap<String, CacheEntry>>>,
+}
+
+impl RevocationCache {
+    fn get(&self, jti: &str) -> Option<bool> {
+        let entries = self.entries.read().unwrap();
+        entries.get(jti)
+            .filter(|entry| entry.revoked || entry.checked_at.elapsed() < CACHE_TTL)
+            .map(|entry| entry.revoked)
+    }
+
+    fn insert(&self, jti: &str, user_id: &str, session_id: &str, issued_at: usize, expires_at: usize, revoked: bool) {
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() >= CACHE_MAX_SIZE {
+            let now = Utc::now().timestamp() as usize;
+            entries.retain(|_, entry| entry.expires_at > now && (entry.revoked || entry.checked_at.elapsed() < CACHE_TTL));
+        }
+        // Still full of live entries: the half checked the longest ago goes
+        if entries.len() >= CACHE_MAX_SIZE {
+            let mut checked: Vec<(Instant, String)> = entries.iter().map(|(jti, entry)| (entry.checked_at, jti.clone())).collect();
+            checked.sort();
+            for (_, jti) in checked.into_iter().take(CACHE_MAX_SIZE / 2) {
+                entries.remove(&jti);
+            }
+        }
+        entries.insert(jti.to_owned(), CacheEntry {
+            user_id: user_id.to_owned(),
+            session_id: session_id.to_owned(),
+            issued_at,
+            expires_at,
+            revoked,
+            checked_at: Instant::now(),
+        });
+    }
+
+    fn revoke_user(&self, user_id: &str, before: usize) {
+        let mut entries = self.entries.write().unwrap();
+        for entry in entries.values_mut().filter(|entry| entry.user_id == user_id && entry.issued_at < before) {
+            entry.revoked = true;
+        }
+    }
+
+    fn revoke_session(&self, session_id: &str) {
+        let mut entries = self.entries.write().unwrap();
+        for entry in entries.values_mut().filter(|entry| entry.session_id == session_id) {
+            entry.revoked = true;
+        }
+    }
+}
+
+
+// Creates the TTL index that removes revocation records once the tokens they refer to have expired
+pub async fn create_indexes(_env: &Environment) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "revoked_tokens",
+        "indexes": [
+            { "key": { "jti": 1 }, "name": "jti" },
+            { "key": { "user_id": 1 }, "name": "user_id" },
+            { "key": { "sid": 1 }, "name": "sid" },
+            { "key": { "expires_at": 1 }, "name": "expires_at_ttl", "expireAfterSeconds": 0 },
+        ]
+    };
+    _env.db().run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [revocation::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Tokens issued before revocation support have no jti or sid, and are neither cached nor matched by them
+pub async fn is_revoked(_env: &Environment, claims: &Claims) -> Result<bool> {
+    if !claims.jti.is_empty() {
+        if let Some(revoked) = _env.revocations().get(&claims.jti) {
+            return Ok(revoked);
+        }
+    }
+
+    let issued_at = claims.issued_at_millis();
+    let mut conditions = vec![doc! { "user_id": &claims.sub, "revoked_before_ms": { "$gt": issued_at as i64 } }];
+    if !claims.jti.is_empty() {
+        conditions.push(doc! { "jti": &claims.jti });
+    }
+    if !claims.sid.is_empty() {
+        conditions.push(doc! { "sid": &claims.sid });
+    }
+    let filter = doc! { "$or": conditions };
+    let found = _env.db().collection("revoked_tokens").find_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [is_revoked] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let revoked = found.is_some();
+    if !claims.jti.is_empty() {
+        _env.revocations().insert(&claims.jti, &claims.sub, &claims.sid, issued_at, claims.exp, revoked);
+    }
+    Ok(revoked)
+}
+
+
+// Revokes a single access token until it expires. Tokens without a jti can only be revoked with all of the user's tokens.
+pub async fn revoke_token(_env: &Environment, user: &AuthUser) -> Result<()> {
+    if user.token_id.is_empty() {
+        return revoke_user_tokens(_env, &user.id).await;
+    }
+    let doc = doc! {
+        "jti": &user.token_id,
+        "user_id": &user.id,
+        "created_at": Utc::now(),
+        "expires_at": Utc.timestamp_opt(user.expires_at as i64, 0).unwrap(),
+    };
+    _env.db().collection("revoked_tokens").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [revoke_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    _env.revocations().insert(&user.token_id, &user.id, &user.session_id, user.issued_at, user.expires_at, true);
+    Ok(())
+}
+
+
+// Revokes every access token issued to the user so far. The record only needs to outlive the longest-lived access token,
+// which may be an impersonation token.
+pub async fn revoke_user_tokens(_env: &Environment, user_id: &str) -> Result<()> {
+    let now = Utc::now();
+    let ttl = _env.config().access_token_ttl.max(_env.config().impersonation_ttl);
+    let doc = doc! {
+        "user_id": user_id,
+        "revoked_before_ms": now.timestamp_millis(),
+        "created_at": now,
+        "expires_at": now + chrono::Duration::seconds(ttl),
+    };
+    _env.db().collection("revoked_tokens").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [revoke_user_tokens] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    _env.revocations().revoke_user(user_id, now.timestamp_millis() as usize);
+    Ok(())
+}
+
+
+// Revokes every access token of the session (refresh token family) `session_id`
+pub async fn revoke_session_tokens(_env: &Environment, user_id: &str, session_id: &str) -> Result<()> {
+    let now = Utc::now();
+    let ttl = _env.config().access_token_ttl.max(_env.config().impersonation_ttl);
+    let doc = doc! {
+        "sid": session_id,
+        "user_id": user_id,
+        "created_at": now,
+        "expires_at": now + chrono::Duration::seconds(ttl),
+    };
+    _env.db().collection("revoked_tokens").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [revoke_session_tokens] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    _env.revocations().revoke_session(session_id);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expires_at() -> usize {
+        Utc::now().timestamp() as usize + 900
+    }
+
+    #[test]
+    fn remembers_verdicts() {
+        let cache = RevocationCache::default();
+        assert_eq!(cache.get("a"), None);
+        cache.insert("a", "user", "session", 1_000, expires_at(), false);
+        cache.insert("b", "user", "session", 1_000, expires_at(), true);
+        assert_eq!(cache.get("a"), Some(false));
+        assert_eq!(cache.get("b"), Some(true));
+    }
+
+    #[test]
+    fn revokes_tokens_of_the_user_issued_before() {
+        let cache = RevocationCache::default();
+        cache.insert("old", "user", "s1", 1_000, expires_at(), false);
+        cache.insert("new", "user", "s1", 3_000, expires_at(), false);
+        cache.insert("other", "someone", "s2", 1_000, expires_at(), false);
+        cache.revoke_user("user", 2_000);
+        assert_eq!(cache.get("old"), Some(true));
+        assert_eq!(cache.get("new"), Some(false));
+        assert_eq!(cache.get("other"), Some(false));
+    }
+
+    #[test]
+    fn revokes_tokens_of_the_session() {
+        let cache = RevocationCache::default();
+        cache.insert("a", "user", "s1", 1_000, expires_at(), false);
+        cache.insert("b", "user", "s2", 1_000, expires_at(), false);
+        cache.revoke_session("s1");
+        assert_eq!(cache.get("a"), Some(true));
+        assert_eq!(cache.get("b"), Some(false));
+    }
+
+    #[test]
+    fn stays_within_its_size() {
+        let cache = RevocationCache::default();
+        for index in 0..=CACHE_MAX_SIZE {
+            cache.insert(&index.to_string(), "user", "session", 1_000, expires_at(), false);
+        }
+        assert!(cache.entries.read().unwrap().len() <= CACHE_MAX_SIZE);
+        assert_eq!(cache.get(&CACHE_MAX_SIZE.to_string()), Some(false));
+    }
+}
@@ -1,8 +1,9 @@
 use warp::{Filter, Reply};
 use warp::filters::BoxedFilter;
 
-use crate::environment;
+use crate::{auth, environment};
 use crate::auth::handlers;
+use crate::roles::{USERS_IMPERSONATE, USERS_MANAGE};
 use crate::environment::Environment;
 
 pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
@@ -10,6 +11,8 @@ pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
         .and(warp::post())
         .and(warp::body::json())
         .and(environment::with_env(_env.clone()))
+        .and(warp::addr::remote())
+        .and(warp::header::headers_cloned())
         .and_then(handlers::login_handler);
 
     let register_route = warp::path!("api" / "auth" / "register")
@@ -18,7 +21,126 @@ pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
         .and(environment::with_env(_env.clone()))
         .and_then(handlers::register_handler);
 
-    let routes = login_route.or(register_route);
+    let refresh_route = warp::path!("api" / "auth" / "refresh")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(warp::header::headers_cloned())
+        .and_then(handlers::refresh_handler);
+
+    let logout_route = warp::path!("api" / "auth" / "logout")
+        .and(warp::post())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::logout_handler);
+
+    let revoke_user_sessions_route = warp::path!("api" / "auth" / "revoke" / String)
+        .and(warp::post())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::revoke_user_sessions_handler);
+
+    // Registered before impersonate_route, which would otherwise take "stop" for a user id
+    let stop_impersonation_route = warp::path!("api" / "auth" / "impersonate" / "stop")
+        .and(warp::post())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::authenticated(_env.clone()))
+        .and_then(handlers::stop_impersonation_handler);
+
+    let impersonate_route = warp::path!("api" / "auth" / "impersonate" / String)
+        .and(warp::post())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_IMPERSONATE))
+        .and_then(handlers::impersonate_handler);
+
+    let forgot_password_route = warp::path!("api" / "auth" / "forgot-password")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and_then(handlers::forgot_password_handler);
+
+    let reset_password_route = warp::path!("api" / "auth" / "reset-password")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and_then(handlers::reset_password_handler);
+
+    let verify_email_route = warp::path!("api" / "auth" / "verify")
+        .and(warp::get())
+        .and(warp::query())
+        .and(environment::with_env(_env.clone()))
+        .and_then(handlers::verify_email_handler);
+
+    let resend_verification_route = warp::path!("api" / "auth" / "verify" / "resend")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and_then(handlers::resend_verification_handler);
+
+    let mfa_login_route = warp::path!("api" / "auth" / "mfa")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(warp::addr::remote())
+        .and(warp::header::headers_cloned())
+        .and_then(handlers::mfa_login_handler);
+
+    let mfa_enroll_route = warp::path!("api" / "auth" / "mfa" / "enroll")
+        .and(warp::post())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::mfa_enroll_handler);
+
+    let mfa_confirm_route = warp::path!("api" / "auth" / "mfa" / "confirm")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::mfa_confirm_handler);
+
+    let mfa_disable_route = warp::path!("api" / "auth" / "mfa" / "disable")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::mfa_disable_handler);
+
+    let oidc_login_route = warp::path!("api" / "auth" / "oidc" / "login")
+        .and(warp::get())
+        .and(environment::with_env(_env.clone()))
+        .and_then(handlers::oidc_login_handler);
+
+    let oidc_callback_route = warp::path!("api" / "auth" / "oidc" / "callback")
+        .and(warp::get())
+        .and(warp::query())
+        .and(environment::with_env(_env.clone()))
+        .and(warp::addr::remote())
+        .and(warp::header::headers_cloned())
+        .and_then(handlers::oidc_callback_handler);
+
+    let jwks_route = warp::path!(".well-known" / "jwks.json")
+        .and(warp::get())
+        .and(environment::with_env(_env.clone()))
+        .and_then(handlers::jwks_handler);
+
+    let routes = login_route
+        .or(register_route)
+        .or(refresh_route)
+        .or(logout_route)
+        .or(revoke_user_sessions_route)
+        .or(stop_impersonation_route)
+        .or(impersonate_route)
+        .or(forgot_password_route)
+        .or(reset_password_route)
+        .or(verify_email_route)
+        .or(resend_verification_route)
+        .or(mfa_login_route)
+        .or(mfa_enroll_route)
+        .or(mfa_confirm_route)
+        .or(mfa_disable_route)
+        .or(oidc_login_route)
+        .or(oidc_callback_route)
+        .or(jwks_route);
     // let routes = login_route;
     routes.boxed()
 }
@@ -0,0 +1,348 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::doc;
+use mongodb::Database;
+
+use crate::{sessions, Result};
+use crate::auth::models::{Mfa, OidcState, RefreshToken};
+use crate::auth::revocation;
+use crate::auth::utils::{doc_to_mfa, doc_to_refresh_token, generate_token, hash_token};
+use crate::environment::Environment;
+use crate::error::AppError;
+
+
+// Creates the indexes required by the auth collections. Expired refresh tokens are removed by MongoDB itself.
+pub async fn create_indexes(_db: Database) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "refresh_tokens",
+        "indexes": [
+            { "key": { "token_hash": 1 }, "name": "token_hash", "unique": true },
+            { "key": { "family_id": 1 }, "name": "family_id" },
+            { "key": { "user_id": 1 }, "name": "user_id" },
+            { "key": { "expires_at": 1 }, "name": "expires_at_ttl", "expireAfterSeconds": 0 },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+
+    let command = doc! {
+        "createIndexes": "mfa",
+        "indexes": [
+            { "key": { "user_id": 1 }, "name": "user_id", "unique": true },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+
+    let command = doc! {
+        "createIndexes": "oidc_states",
+        "indexes": [
+            { "key": { "state_hash": 1 }, "name": "state_hash", "unique": true },
+            { "key": { "expires_at": 1 }, "name": "expires_at_ttl", "expireAfterSeconds": 0 },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+
+    let command = doc! {
+        "createIndexes": "user_tokens",
+        "indexes": [
+            { "key": { "token_hash": 1 }, "name": "token_hash", "unique": true },
+            { "key": { "user_id": 1, "purpose": 1 }, "name": "user_id_purpose" },
+            { "key": { "expires_at": 1 }, "name": "expires_at_ttl", "expireAfterSeconds": 0 },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Issues a new refresh token in the given family and returns its raw value, which is never stored
+pub async fn create_refresh_token(user_id: &str, family_id: &str, expires_at: DateTime<Utc>, _db: Database) -> Result<String> {
+    let token = generate_token();
+    let doc = doc! {
+        "token_hash": hash_token(&token),
+        "family_id": family_id,
+        "user_id": user_id,
+        "created_at": Utc::now(),
+        "expires_at": expires_at,
+    };
+    _db.collection("refresh_tokens").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [create_refresh_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(token)
+}
+
+
+// Marks a refresh token as used and returns it. A token that was already used means it was replayed, in which case
+// the whole session is signed out, access tokens included, so that neither the attacker nor the victim can keep using it.
+pub async fn use_refresh_token(_env: &Environment, token: &str) -> Result<RefreshToken> {
+    let _db = _env.db();
+    let token_hash = hash_token(token);
+    let filter = doc! { "token_hash": &token_hash, "used_at": null, "revoked_at": null };
+    let updates = doc! { "$set": { "used_at": Utc::now() } };
+    let updated = _db.collection("refresh_tokens").find_one_and_update(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [use_refresh_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+
+    if let Some(doc) = updated {
+        let refresh_token = doc_to_refresh_token(&doc)?;
+        if refresh_token.expires_at < Utc::now() {
+            return Err(AppError::RefreshTokenError);
+        }
+        return Ok(refresh_token);
+    }
+
+    let filter = doc! { "token_hash": &token_hash };
+    let existing = _db.collection("refresh_tokens").find_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [use_refresh_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if let Some(doc) = existing {
+        let refresh_token = doc_to_refresh_token(&doc)?;
+        println!("[use_refresh_token] Reuse detected for user {}, ending session {}", &refresh_token.user_id, &refresh_token.family_id);
+        match sessions::service::end_session(_env, &refresh_token.user_id, &refresh_token.family_id).await {
+            Ok(_) => (),
+            // Already signed out, or a login made before sessions were recorded, whose tokens are revoked all the same
+            Err(AppError::SessionNotFoundError) => {
+                revoke_refresh_token_family(&refresh_token.family_id, _env.db()).await?;
+                revocation::revoke_session_tokens(_env, &refresh_token.user_id, &refresh_token.family_id).await?;
+            },
+            Err(_e) => return Err(_e),
+        }
+    }
+    Err(AppError::RefreshTokenError)
+}
+
+
+pub async fn revoke_refresh_token_family(family_id: &str, _db: Database) -> Result<()> {
+    let filter = doc! { "family_id": family_id, "revoked_at": null };
+    let updates = doc! { "$set": { "revoked_at": Utc::now() } };
+    _db.collection("refresh_tokens").update_many(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [revoke_refresh_token_family] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+
+pub async fn revoke_user_refresh_tokens(user_id: &str, _db: Database) -> Result<()> {
+    let filter = doc! { "user_id": user_id, "revoked_at": null };
+    let updates = doc! { "$set": { "revoked_at": Utc::now() } };
+    _db.collection("refresh_tokens").update_many(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [revoke_user_refresh_tokens] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Revokes the refresh tokens of the user, except for those of the family `family_id`
+pub async fn revoke_other_refresh_tokens(user_id: &str, family_id: &str, _db: Database) -> Result<()> {
+    let filter = doc! { "user_id": user_id, "family_id": { "$ne": family_id }, "revoked_at": null };
+    let updates = doc! { "$set": { "revoked_at": Utc::now() } };
+    _db.collection("refresh_tokens").update_many(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [revoke_other_refresh_tokens] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Issues a single-use token for an emailed link (e.g. password reset), replacing any unused token for the same purpose
+pub async fn create_user_token(user_id: &str, purpose: &str, expires_at: DateTime<Utc>, _db: Database) -> Result<String> {
+    let filter = doc! { "user_id": user_id, "purpose": purpose, "used_at": null };
+    _db.collection("user_tokens").delete_many(filter, None).await.map_err(|_e| {
+        println!("ERROR [create_user_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+
+    let token = generate_token();
+    let doc = doc! {
+        "token_hash": hash_token(&token),
+        "user_id": user_id,
+        "purpose": purpose,
+        "created_at": Utc::now(),
+        "expires_at": expires_at,
+    };
+    _db.collection("user_tokens").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [create_user_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(token)
+}
+
+
+// Returns the id of the user a valid token was issued to, without using it up
+pub async fn find_user_token(token: &str, purpose: &str, _db: Database) -> Result<String> {
+    let filter = doc! {
+        "token_hash": hash_token(token),
+        "purpose": purpose,
+        "used_at": null,
+        "expires_at": { "$gt": Utc::now() },
+    };
+    let found = _db.collection("user_tokens").find_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [find_user_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    match found {
+        Some(doc) => Ok(doc.get_str("user_id")?.to_owned()),
+        None => Err(AppError::InvalidUserTokenError),
+    }
+}
+
+
+// Atomically marks a valid token as used and returns the id of the user it was issued to
+pub async fn consume_user_token(token: &str, purpose: &str, _db: Database) -> Result<String> {
+    let filter = doc! {
+        "token_hash": hash_token(token),
+        "purpose": purpose,
+        "used_at": null,
+        "expires_at": { "$gt": Utc::now() },
+    };
+    let updates = doc! { "$set": { "used_at": Utc::now() } };
+    let updated = _db.collection("user_tokens").find_one_and_update(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [consume_user_token] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    match updated {
+        Some(doc) => Ok(doc.get_str("user_id")?.to_owned()),
+        None => Err(AppError::InvalidUserTokenError),
+    }
+}
+
+
+// Two-factor authentication
+
+// TOTP secrets are stored encrypted with the server key and decrypted when read
+pub async fn get_mfa(_env: &Environment, user_id: &str) -> Result<Option<Mfa>> {
+    let filter = doc! { "user_id": user_id };
+    let found = _env.db().collection("mfa").find_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [get_mfa] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut mfa = match found {
+        Some(doc) => doc_to_mfa(&doc)?,
+        None => return Ok(None),
+    };
+    if let Some(secret) = &mfa.secret {
+        mfa.secret = Some(_env.secret_box().decrypt(secret)?);
+    }
+    if let Some(secret) = &mfa.pending_secret {
+        mfa.pending_secret = Some(_env.secret_box().decrypt(secret)?);
+    }
+    Ok(Some(mfa))
+}
+
+
+pub async fn set_mfa_pending_secret(_env: &Environment, user_id: &str, secret: &str) -> Result<()> {
+    let filter = doc! { "user_id": user_id };
+    let updates = doc! { "$set": { "pending_secret": _env.secret_box().encrypt(secret)?, "updated_at": Utc::now() } };
+    let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+    _env.db().collection("mfa").update_one(filter, updates, options).await.map_err(|_e| {
+        println!("ERROR [set_mfa_pending_secret] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+pub async fn enable_mfa(_env: &Environment, user_id: &str, secret: &str, step: i64, recovery_code_hashes: Vec<String>) -> Result<()> {
+    let filter = doc! { "user_id": user_id };
+    let updates = doc! {
+        "$set": {
+            "secret": _env.secret_box().encrypt(secret)?,
+            "enabled_at": Utc::now(),
+            "last_step": step,
+            "recovery_codes": recovery_code_hashes,
+            "updated_at": Utc::now(),
+        },
+        "$unset": { "pending_secret": "" },
+    };
+    _env.db().collection("mfa").update_one(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [enable_mfa] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+pub async fn disable_mfa(user_id: &str, _db: Database) -> Result<()> {
+    let filter = doc! { "user_id": user_id };
+    _db.collection("mfa").delete_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [disable_mfa] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Records the TOTP step as used. Returns false when the same or a later step was already accepted.
+pub async fn use_mfa_step(user_id: &str, step: i64, _db: Database) -> Result<bool> {
+    let filter = doc! { "user_id": user_id, "last_step": { "$lt": step } };
+    let updates = doc! { "$set": { "last_step": step } };
+    let result = _db.collection("mfa").update_one(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [use_mfa_step] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(result.modified_count == 1)
+}
+
+
+// Removes the recovery code so it cannot be used again. Returns false when the code is unknown.
+pub async fn use_recovery_code(user_id: &str, code_hash: &str, _db: Database) -> Result<bool> {
+    let filter = doc! { "user_id": user_id, "recovery_codes": code_hash };
+    let updates = doc! { "$pull": { "recovery_codes": code_hash } };
+    let result = _db.collection("mfa").update_one(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [use_recovery_code] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(result.modified_count == 1)
+}
+
+
+pub async fn create_oidc_state(state: &str, oidc_state: &OidcState, expires_at: DateTime<Utc>, _db: Database) -> Result<()> {
+    let doc = doc! {
+        "state_hash": hash_token(state),
+        "nonce": &oidc_state.nonce,
+        "code_verifier": &oidc_state.code_verifier,
+        "created_at": Utc::now(),
+        "expires_at": expires_at,
+    };
+    _db.collection("oidc_states").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [create_oidc_state] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Each state can complete a single callback
+pub async fn consume_oidc_state(state: &str, _db: Database) -> Result<OidcState> {
+    let filter = doc! {
+        "state_hash": hash_token(state),
+        "expires_at": { "$gt": Utc::now() },
+    };
+    let deleted = _db.collection("oidc_states").find_one_and_delete(filter, None).await.map_err(|_e| {
+        println!("ERROR [consume_oidc_state] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    match deleted {
+        Some(doc) => Ok(OidcState {
+            nonce: doc.get_str("nonce")?.to_owned(),
+            code_verifier: doc.get_str("code_verifier")?.to_owned(),
+        }),
+        None => Err(AppError::OidcError),
+    }
+}
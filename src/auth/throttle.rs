@@ -0,0 +1,227 @@
+use std::net::SocketAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use mongodb::bson::{doc, Document};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use warp::http::{HeaderMap, HeaderValue};
+
+use crate::{users, Result};
+use crate::environment::Environment;
+use crate::error::AppError;
+
+// Failed logins are tracked both per account and per client IP. After a few failures each one delays the next attempt
+// exponentially, and reaching the threshold locks the key out for the configured duration.
+const BACKOFF_FREE_FAILURES: i64 = 3;
+const MAX_BACKOFF_SECONDS: i64 = 300;
+
+
+pub fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+
+pub fn ip_key(addr: Option<SocketAddr>, headers: &HeaderMap<HeaderValue>, _env: &Environment) -> Option<String> {
+    client_ip(addr, headers, _env).map(|ip| format!("ip:{}", ip))
+}
+
+
+pub fn client_ip(addr: Option<SocketAddr>, headers: &HeaderMap<HeaderValue>, _env: &Environment) -> Option<String> {
+    // Behind a reverse proxy every request comes from the proxy, so the client address is taken from the proxy header
+    if _env.config().trust_forwarded_for {
+        if let Some(ip) = forwarded_ip(headers) {
+            return Some(ip);
+        }
+    }
+    addr.map(|addr| addr.ip().to_string())
+}
+
+
+// The last X-Forwarded-For entry is the one appended by the proxy. Those before it come from the client and can be anything.
+fn forwarded_ip(headers: &HeaderMap<HeaderValue>) -> Option<String> {
+    headers.get_all("x-forwarded-for").iter().last()
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit(',').next())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_owned)
+}
+
+
+pub async fn create_indexes(_env: &Environment) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "login_attempts",
+        "indexes": [
+            { "key": { "key": 1 }, "name": "key", "unique": true },
+            { "key": { "expires_at": 1 }, "name": "expires_at_ttl", "expireAfterSeconds": 0 },
+        ]
+    };
+    _env.db().run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [throttle::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Reserves an attempt on each key before the credentials are checked, counting it as a failure until it is released.
+// The count and the restrictions are checked in the same update, so concurrent attempts cannot slip past the threshold.
+// Rejects the attempt when any of the keys is locked out, backing off or has as many pending attempts as its threshold.
+pub async fn reserve(_env: &Environment, keys: &[String]) -> Result<()> {
+    let config = _env.config();
+    let now = Utc::now();
+    for (index, key) in keys.iter().enumerate() {
+        let filter = doc! {
+            "key": key,
+            "failures": { "$not": { "$gte": threshold(_env, key) } },
+            "locked_until": { "$not": { "$gt": now } },
+            "blocked_until": { "$not": { "$gt": now } },
+        };
+        let updates = doc! {
+            "$inc": { "failures": 1 },
+            "$set": { "expires_at": now + Duration::seconds(config.login_lockout_duration) },
+        };
+        let options = FindOneAndUpdateOptions::builder().upsert(true).build();
+        match _env.db().collection("login_attempts").find_one_and_update(filter, updates, options).await {
+            Ok(_) => (),
+            // The record exists but did not match: the key is restricted
+            Err(_e) if users::utils::is_duplicate_key(&_e) => {
+                release(_env, &keys[..index]).await?;
+                return Err(restriction_error(_env, key, now).await?);
+            },
+            Err(_e) => {
+                println!("ERROR [throttle::reserve] {:?}", _e);
+                return Err(AppError::DataError);
+            },
+        }
+    }
+    Ok(())
+}
+
+
+// Gives back the attempts reserved on the keys, once the credentials turned out to be valid
+pub async fn release(_env: &Environment, keys: &[String]) -> Result<()> {
+    for key in keys {
+        let filter = doc! { "key": key, "failures": { "$gt": 0 } };
+        _env.db().collection("login_attempts").update_one(filter, doc! { "$inc": { "failures": -1 } }, None).await.map_err(|_e| {
+            println!("ERROR [throttle::release] {:?}", _e);
+            return AppError::DataError;
+        })?;
+    }
+    Ok(())
+}
+
+
+async fn restriction_error(_env: &Environment, key: &str, now: DateTime<Utc>) -> Result<AppError> {
+    let doc = _env.db().collection("login_attempts").find_one(doc! { "key": key }, None).await.map_err(|_e| {
+        println!("ERROR [throttle::reserve] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if matches!(doc.as_ref().map(|doc| doc.get_datetime("locked_until")), Some(Ok(until)) if *until > now) {
+        println!("[throttle::reserve] {} is locked out", key);
+        return Ok(if key.starts_with("account:") { AppError::AccountLockedError } else { AppError::TooManyLoginAttemptsError });
+    }
+    println!("[throttle::reserve] {} is backing off", key);
+    Ok(AppError::TooManyLoginAttemptsError)
+}
+
+
+// Keeps the reserved attempts as failures, and restricts the keys according to their failure count
+pub async fn record_failure(_env: &Environment, keys: &[String]) -> Result<()> {
+    let config = _env.config();
+    let now = Utc::now();
+    for key in keys {
+        let filter = doc! { "key": key };
+        let updates = doc! { "$set": { "last_failure_at": now } };
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        let updated = _env.db().collection("login_attempts").find_one_and_update(filter.clone(), updates, options).await.map_err(|_e| {
+            println!("ERROR [throttle::record_failure] {:?}", _e);
+            return AppError::DataError;
+        })?;
+        let failures = updated.as_ref().map_or(1, failure_count);
+
+        let mut restriction = Document::new();
+        if let Some(until) = backoff_until(now, failures) {
+            restriction.insert("blocked_until", until);
+        }
+        if failures >= threshold(_env, key) {
+            println!("[throttle::record_failure] Locking out {} after {} failures", key, failures);
+            restriction.insert("locked_until", now + Duration::seconds(config.login_lockout_duration));
+            restriction.insert("failures", 0);
+        }
+        if restriction.is_empty() {
+            continue;
+        }
+        _env.db().collection("login_attempts").update_one(filter, doc! { "$set": restriction }, None).await.map_err(|_e| {
+            println!("ERROR [throttle::record_failure] {:?}", _e);
+            return AppError::DataError;
+        })?;
+    }
+    Ok(())
+}
+
+
+// Forgets previous failures, after a successful login or when an admin unlocks the account
+pub async fn reset(_env: &Environment, key: &str) -> Result<()> {
+    let filter = doc! { "key": key };
+    _env.db().collection("login_attempts").delete_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [throttle::reset] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+fn threshold(_env: &Environment, key: &str) -> i64 {
+    if key.starts_with("account:") { _env.config().login_lockout_threshold } else { _env.config().login_ip_lockout_threshold }
+}
+
+
+fn failure_count(doc: &Document) -> i64 {
+    doc.get_i32("failures").map(i64::from).or_else(|_| doc.get_i64("failures")).unwrap_or(1)
+}
+
+
+// None for the first BACKOFF_FREE_FAILURES failures, then 1s, 2s, 4s... up to MAX_BACKOFF_SECONDS
+fn backoff_until(now: DateTime<Utc>, failures: i64) -> Option<DateTime<Utc>> {
+    if failures <= BACKOFF_FREE_FAILURES {
+        return None;
+    }
+    let exponent = (failures - BACKOFF_FREE_FAILURES - 1).clamp(0, 16) as u32;
+    Some(now + Duration::seconds(2i64.pow(exponent).min(MAX_BACKOFF_SECONDS)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(forwarded_for: &[&str]) -> HeaderMap<HeaderValue> {
+        let mut headers = HeaderMap::new();
+        for value in forwarded_for {
+            headers.append("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn takes_the_entry_appended_by_the_proxy() {
+        assert_eq!(forwarded_ip(&headers(&["203.0.113.7"])), Some("203.0.113.7".to_owned()));
+        assert_eq!(forwarded_ip(&headers(&["1.2.3.4, 203.0.113.7"])), Some("203.0.113.7".to_owned()));
+        assert_eq!(forwarded_ip(&headers(&["1.2.3.4", "203.0.113.7"])), Some("203.0.113.7".to_owned()));
+    }
+
+    #[test]
+    fn ignores_missing_or_empty_entries() {
+        assert_eq!(forwarded_ip(&headers(&[])), None);
+        assert_eq!(forwarded_ip(&headers(&["1.2.3.4, "])), None);
+    }
+
+    #[test]
+    fn backs_off_exponentially_after_the_free_failures() {
+        let now = Utc::now();
+        assert_eq!(backoff_until(now, BACKOFF_FREE_FAILURES), None);
+        assert_eq!(backoff_until(now, BACKOFF_FREE_FAILURES + 1), Some(now + Duration::seconds(1)));
+        assert_eq!(backoff_until(now, BACKOFF_FREE_FAILURES + 3), Some(now + Duration::seconds(4)));
+        assert_eq!(backoff_until(now, 1000), Some(now + Duration::seconds(MAX_BACKOFF_SECONDS)));
+    }
+}
@@ -0,0 +1,113 @@
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use sha1::Sha1;
+
+// RFC 6238 defaults, which is what authenticator apps expect
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+// Accept codes from one step before and after the current one to tolerate clock drift
+const ALLOWED_DRIFT: i64 = 1;
+
+
+// Generates a random 160-bit secret, base32 encoded as expected by authenticator apps
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes)
+}
+
+
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        url_encode(issuer), url_encode(account), secret, url_encode(issuer), DIGITS, STEP_SECONDS)
+}
+
+
+// Returns the time step matched by the code, so callers can refuse to accept the same step twice
+pub fn verify(secret: &str, code: &str, timestamp: i64) -> Option<i64> {
+    let key = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let current = timestamp / STEP_SECONDS;
+    (current - ALLOWED_DRIFT..=current + ALLOWED_DRIFT).find(|step| generate(&key, *step) == code)
+}
+
+
+fn generate(key: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&(step as u64).to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation as described in RFC 4226
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+
+fn url_encode(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 seed of RFC 6238 appendix B, "12345678901234567890", base32 encoded
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        let key = base32::decode(base32::Alphabet::RFC4648 { padding: false }, SECRET).unwrap();
+        // The RFC lists 8 digit codes, whose last 6 digits are the 6 digit codes
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        for (timestamp, code) in vectors.iter() {
+            assert_eq!(generate(&key, timestamp / STEP_SECONDS), code[2..], "at {}", timestamp);
+        }
+    }
+
+    #[test]
+    fn verifies_codes_within_the_allowed_drift() {
+        assert_eq!(verify(SECRET, "081804", 1111111109), Some(37037036));
+        assert_eq!(verify(SECRET, "081804", 1111111109 + STEP_SECONDS), Some(37037036));
+        assert_eq!(verify(SECRET, "081804", 1111111109 - STEP_SECONDS), Some(37037036));
+        assert_eq!(verify(SECRET, "081804", 1111111109 + 2 * STEP_SECONDS), None);
+        assert_eq!(verify(SECRET, " 081804 ", 1111111109), Some(37037036));
+    }
+
+    #[test]
+    fn refuses_malformed_codes_and_secrets() {
+        assert_eq!(verify(SECRET, "08180", 1111111109), None);
+        assert_eq!(verify(SECRET, "0818045", 1111111109), None);
+        assert_eq!(verify(SECRET, "08180a", 1111111109), None);
+        assert_eq!(verify("not base32!", "081804", 1111111109), None);
+    }
+
+    #[test]
+    fn generates_base32_secrets() {
+        let secret = generate_secret();
+        assert_eq!(base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret).map(|key| key.len()), Some(20));
+        assert_ne!(secret, generate_secret());
+    }
+
+    #[test]
+    fn encodes_the_otpauth_uri() {
+        assert_eq!(
+            otpauth_uri("Demo API", "jane@example.com", SECRET),
+            "otpauth://totp/Demo%20API:jane%40example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Demo%20API&algorithm=SHA1&digits=6&period=30");
+    }
+}
@@ -0,0 +1,60 @@
+use mongodb::bson::Document;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+use crate::auth::models::{Mfa, RefreshToken};
+
+
+// Generates an opaque, URL-safe random token
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+
+// Opaque tokens are only stored as their SHA-256 digest, so a database leak does not expose usable tokens
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+
+
+// Generates a human friendly one-time recovery code such as `k3m9q-7xw2d`
+pub fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 7];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = base32::encode(base32::Alphabet::Crockford, &bytes).to_lowercase();
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+
+// Recovery codes are hashed like other tokens, ignoring case and the separator
+pub fn hash_recovery_code(code: &str) -> String {
+    hash_token(&code.trim().to_lowercase().replace('-', ""))
+}
+
+
+pub fn doc_to_refresh_token(doc: &Document) -> Result<RefreshToken> {
+    let result = RefreshToken {
+        id: doc.get_object_id("_id")?.to_string(),
+        family_id: doc.get_str("family_id")?.to_owned(),
+        user_id: doc.get_str("user_id")?.to_owned(),
+        created_at: *doc.get_datetime("created_at")?,
+        expires_at: *doc.get_datetime("expires_at")?,
+    };
+    Ok(result)
+}
+
+
+pub fn doc_to_mfa(doc: &Document) -> Result<Mfa> {
+    let result = Mfa {
+        user_id: doc.get_str("user_id")?.to_owned(),
+        secret: doc.get_str("secret").ok().map(str::to_owned),
+        pending_secret: doc.get_str("pending_secret").ok().map(str::to_owned),
+        enabled_at: doc.get_datetime("enabled_at").ok().copied(),
+        last_step: doc.get_i64("last_step").unwrap_or(0),
+    };
+    Ok(result)
+}
@@ -0,0 +1,34 @@
+use warp::Reply;
+use warp::multipart::FormData;
+use warp::reject;
+
+use crate::{users, WebResult};
+use crate::auth::models::AuthUser;
+use crate::avatars::{service, utils, AVATAR_SIZES};
+use crate::avatars::models::AvatarQuery;
+use crate::environment::Environment;
+
+// Sets the avatar of the current user from a multipart upload, and returns the updated user
+pub async fn upload_avatar_handler(_form: FormData, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let data = utils::read_avatar(_form).await.map_err(reject::custom)?;
+    service::set_avatar(&_env, &_user.id, data).await.map_err(reject::custom)?;
+    println!("[upload_avatar_handler] User {} uploaded an avatar", _user);
+    let result = users::service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+pub async fn delete_avatar_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    service::delete_avatar(&_env, &_user.id).await.map_err(reject::custom)?;
+    println!("[delete_avatar_handler] User {} removed their avatar", _user);
+    let result = users::service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+// Serves the avatar of a user. Its URL changes with every upload, so it can be cached for long.
+pub async fn get_avatar_handler(_id: String, _query: AvatarQuery, _env: Environment) -> WebResult<impl Reply> {
+    let size = _query.size.unwrap_or_else(|| AVATAR_SIZES[0].0.to_owned());
+    let data = service::get_avatar(&_env, &_id, &size).await.map_err(reject::custom)?;
+    let reply = warp::reply::with_header(data, "content-type", "image/png");
+    let reply = warp::reply::with_header(reply, "cache-control", "public, max-age=86400");
+    Ok(warp::reply::with_header(reply, "x-content-type-options", "nosniff"))
+}
@@ -0,0 +1,12 @@
+pub mod handlers;
+pub mod models;
+pub mod routes;
+pub mod service;
+pub mod utils;
+
+// Largest accepted upload, and largest width or height of the uploaded image
+pub const AVATAR_MAX_BYTES: u64 = 2 * 1024 * 1024;
+pub const AVATAR_MAX_DIMENSION: u32 = 4096;
+
+// Square PNG thumbnails kept of every avatar, by name and size in pixels. The first one is served by default.
+pub const AVATAR_SIZES: [(&str, u32); 2] = [("large", 256), ("small", 64)];
@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct AvatarQuery {
+    // large (default) or small
+    pub size: Option<String>,
+}
@@ -0,0 +1,32 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::avatars::{handlers, AVATAR_MAX_BYTES};
+use crate::environment::Environment;
+
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    // Leaves room for the multipart boundaries and headers around the image
+    let upload_avatar_route = warp::put().and(warp::path!("api" / "users" / "me" / "avatar")
+        .and(warp::multipart::form().max_length(AVATAR_MAX_BYTES + 64 * 1024))
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::authenticated(_env.clone()))
+        .and_then(handlers::upload_avatar_handler));
+
+    let delete_avatar_route = warp::delete().and(warp::path!("api" / "users" / "me" / "avatar")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::authenticated(_env.clone()))
+        .and_then(handlers::delete_avatar_handler));
+
+    // Public, so that avatars can be shown with plain image tags
+    let get_avatar_route = warp::get().and(warp::path!("api" / "users" / String / "avatar")
+        .and(warp::query())
+        .and(environment::with_env(_env.clone()))
+        .and_then(handlers::get_avatar_handler));
+
+    let routes = upload_avatar_route
+        .or(delete_avatar_route)
+        .or(get_avatar_route);
+
+    routes.boxed()
+}
@@ -0,0 +1,48 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+
+use crate::{users, Result};
+use crate::avatars::AVATAR_SIZES;
+use crate::avatars::utils::{avatar_key, make_thumbnails};
+use crate::environment::Environment;
+use crate::error::AppError;
+
+
+// Stores the thumbnails of the uploaded image as the avatar of the user, replacing any previous one
+pub async fn set_avatar(_env: &Environment, user_id: &str, data: Vec<u8>) -> Result<()> {
+    let thumbnails = tokio::task::spawn_blocking(move || make_thumbnails(&data)).await.map_err(|_e| {
+        println!("ERROR [set_avatar] {:?}", _e);
+        return AppError::DataError;
+    })??;
+    for (size, png) in thumbnails {
+        _env.storage().put(&avatar_key(user_id, size), png).await?;
+    }
+    users::service::set_avatar_updated_at(user_id, Some(Utc::now()), _env.db()).await
+}
+
+
+// Returns the PNG thumbnail of the given size
+pub async fn get_avatar(_env: &Environment, user_id: &str, size: &str) -> Result<Vec<u8>> {
+    // Ids become part of the storage key, so only well-formed ones are looked up
+    ObjectId::with_string(user_id).map_err(|_| AppError::AvatarNotFoundError)?;
+    if !AVATAR_SIZES.iter().any(|(name, _)| *name == size) {
+        return Err(AppError::AvatarNotFoundError);
+    }
+    _env.storage().get(&avatar_key(user_id, size)).await?.ok_or(AppError::AvatarNotFoundError)
+}
+
+
+// Removes the avatar of the user, if they have one
+pub async fn delete_avatar(_env: &Environment, user_id: &str) -> Result<()> {
+    delete_avatar_files(_env, user_id).await?;
+    users::service::set_avatar_updated_at(user_id, None, _env.db()).await
+}
+
+
+// Removes the stored thumbnails only, as when the user is deleted
+pub async fn delete_avatar_files(_env: &Environment, user_id: &str) -> Result<()> {
+    for (size, _) in AVATAR_SIZES.iter() {
+        _env.storage().delete(&avatar_key(user_id, size)).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, ImageOutputFormat};
+use image::imageops::FilterType;
+use tokio::stream::StreamExt;
+use warp::hyper::body::Buf;
+use warp::multipart::FormData;
+
+use crate::Result;
+use crate::avatars::{AVATAR_MAX_BYTES, AVATAR_MAX_DIMENSION, AVATAR_SIZES};
+use crate::error::{AppError, FieldError};
+
+// Formats accepted for uploads, whatever content type the client gave
+const AVATAR_FORMATS: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif];
+
+
+// Reads the `avatar` part of an upload
+pub async fn read_avatar(mut form: FormData) -> Result<Vec<u8>> {
+    while let Some(part) = form.next().await {
+        let mut part = part.map_err(|_e| {
+            println!("ERROR [read_avatar] {:?}", _e);
+            return invalid("invalid", "must be a multipart form");
+        })?;
+        if part.name() != "avatar" {
+            continue;
+        }
+        let mut data: Vec<u8> = Vec::new();
+        while let Some(chunk) = part.data().await {
+            let chunk = chunk.map_err(|_e| {
+                println!("ERROR [read_avatar] {:?}", _e);
+                return invalid("invalid", "could not be read");
+            })?;
+            data.extend_from_slice(chunk.bytes());
+            if data.len() as u64 > AVATAR_MAX_BYTES {
+                return Err(invalid("too_large", &format!("must be at most {} bytes", AVATAR_MAX_BYTES)));
+            }
+        }
+        return Ok(data);
+    }
+    Err(invalid("required", "must be uploaded as the avatar part of a multipart form"))
+}
+
+
+// Checks the image and makes its thumbnails, re-encoded as PNG, which also leaves out any metadata of the upload.
+// Blocking, run it on the blocking thread pool.
+pub fn make_thumbnails(data: &[u8]) -> Result<Vec<(&'static str, Vec<u8>)>> {
+    let format = match image::guess_format(data) {
+        Ok(format) if AVATAR_FORMATS.contains(&format) => format,
+        _ => return Err(invalid("unsupported_type", "must be a PNG, JPEG or GIF image")),
+    };
+    // Checked before decoding, as a small file can hold a huge image
+    let (width, height) = image::io::Reader::with_format(Cursor::new(data), format).into_dimensions()
+        .map_err(|_| invalid("invalid_image", "could not be read as an image"))?;
+    if width > AVATAR_MAX_DIMENSION || height > AVATAR_MAX_DIMENSION {
+        return Err(invalid("too_large", &format!("must be at most {} pixels wide and high", AVATAR_MAX_DIMENSION)));
+    }
+    let image = image::io::Reader::with_format(Cursor::new(data), format).decode()
+        .map_err(|_| invalid("invalid_image", "could not be read as an image"))?;
+
+    let mut thumbnails = Vec::new();
+    for (name, size) in AVATAR_SIZES.iter() {
+        let mut png: Vec<u8> = Vec::new();
+        image.resize_to_fill(*size, *size, FilterType::Lanczos3).write_to(&mut png, ImageOutputFormat::Png).map_err(|_e| {
+            println!("ERROR [make_thumbnails] {:?}", _e);
+            return AppError::DataError;
+        })?;
+        thumbnails.push((*name, png));
+    }
+    Ok(thumbnails)
+}
+
+
+pub fn avatar_key(user_id: &str, size: &str) -> String {
+    format!("avatars/{}/{}.png", user_id, size)
+}
+
+
+fn invalid(code: &str, message: &str) -> AppError {
+    AppError::ValidationError(vec![FieldError::new("avatar", code, message)])
+}
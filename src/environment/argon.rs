@@ -1,4 +1,8 @@
+use argonautica::config::{DEFAULT_ITERATIONS, DEFAULT_MEMORY_SIZE};
+
+use crate::Result;
 use crate::environment::Args;
+use crate::error::AppError;
 
 #[derive(Clone, Debug)]
 pub struct Argon {
@@ -34,9 +38,81 @@ impl Argon {
         hasher.to_owned()
     }
 
+    // Hashes on the blocking thread pool, for callers hashing many passwords in a row such as imports
+    pub async fn hash(&self, password: &str) -> Result<String> {
+        let mut hasher = self.hasher();
+        let password = password.to_owned();
+        tokio::task::spawn_blocking(move || hasher.with_password(&password).hash()).await.map_err(|_e| {
+            println!("ERROR [argon::hash] {:?}", _e);
+            return AppError::ArgonError;
+        })?.or(Err(AppError::ArgonError))
+    }
+
     pub fn verifier(&self) -> argonautica::Verifier<'static> {
         let mut verifier = argonautica::Verifier::default();
         let verifier = verifier.with_secret_key(&self.secret);
         verifier.to_owned()
     }
+
+    // Whether a stored hash was made with other memory or iteration settings than the current ones
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let memory_size = self.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+        let iterations = self.iterations.unwrap_or(DEFAULT_ITERATIONS);
+        // $argon2id$v=19$m=4096,t=192,p=4$salt$hash
+        let params = match hash.split('$').nth(3) {
+            Some(params) => params,
+            None => return true,
+        };
+        let mut hash_memory_size = None;
+        let mut hash_iterations = None;
+        for param in params.split(',') {
+            match param.split_once('=') {
+                Some(("m", value)) => hash_memory_size = value.parse::<u32>().ok(),
+                Some(("t", value)) => hash_iterations = value.parse::<u32>().ok(),
+                _ => (),
+            }
+        }
+        hash_memory_size != Some(memory_size) || hash_iterations != Some(iterations)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argon(memory_size: Option<u32>, iterations: Option<u32>) -> Argon {
+        Argon { secret: "secret".to_owned(), memory_size, iterations }
+    }
+
+    #[test]
+    fn keeps_hashes_with_the_current_settings() {
+        let hash = "$argon2id$v=19$m=4096,t=192,p=4$c2FsdHNhbHQ$aGFzaGhhc2g";
+        assert!(!argon(Some(4096), Some(192)).needs_rehash(hash));
+        // The parameters may come in any order
+        assert!(!argon(Some(4096), Some(192)).needs_rehash("$argon2id$v=19$p=4,t=192,m=4096$c2FsdHNhbHQ$aGFzaGhhc2g"));
+    }
+
+    #[test]
+    fn rehashes_when_the_settings_changed() {
+        let hash = "$argon2id$v=19$m=4096,t=192,p=4$c2FsdHNhbHQ$aGFzaGhhc2g";
+        assert!(argon(Some(8192), Some(192)).needs_rehash(hash));
+        assert!(argon(Some(4096), Some(3)).needs_rehash(hash));
+    }
+
+    #[test]
+    fn compares_with_the_defaults_when_not_configured() {
+        let hash = format!("$argon2id$v=19$m={},t={},p=4$c2FsdHNhbHQ$aGFzaGhhc2g", DEFAULT_MEMORY_SIZE, DEFAULT_ITERATIONS);
+        assert!(!argon(None, None).needs_rehash(&hash));
+        assert!(argon(None, Some(DEFAULT_ITERATIONS + 1)).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn rehashes_unreadable_hashes() {
+        let argon = argon(Some(4096), Some(192));
+        assert!(argon.needs_rehash(""));
+        assert!(argon.needs_rehash("$argon2id$v=19"));
+        assert!(argon.needs_rehash("$argon2id$v=19$m=4096$c2FsdHNhbHQ$aGFzaGhhc2g"));
+        assert!(argon.needs_rehash("$argon2id$v=19$m=lots,t=192,p=4$c2FsdHNhbHQ$aGFzaGhhc2g"));
+    }
 }
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use serde_json::{json, Value};
+use simple_asn1::{ASN1Block, oid};
+
+use crate::environment::Args;
+
+#[derive(Clone)]
+struct JwtKey {
+    algorithm: Algorithm,
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    // Public key in JWK format, only available for asymmetric keys
+    jwk: Option<Value>,
+}
+
+// Signing keys indexed by their `kid`. New tokens are always signed with the active key, while
+// retired keys are kept only to verify tokens issued before a rotation until they expire.
+#[derive(Clone)]
+pub struct JwtKeys {
+    active_kid: String,
+    keys: HashMap<String, JwtKey>,
+}
+
+impl std::fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut kids: Vec<&String> = self.keys.keys().collect();
+        kids.sort();
+        f.debug_struct("JwtKeys")
+            .field("active_kid", &self.active_kid)
+            .field("kids", &kids)
+            .finish()
+    }
+}
+
+impl JwtKeys {
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let Args {
+            jwt_algorithm,
+            jwt_secret,
+            jwt_key_id,
+            jwt_retired_keys,
+            jwt_private_key,
+            jwt_public_key,
+            jwt_retired_public_keys,
+            ..
+        } = args;
+
+        let mut keys = HashMap::new();
+        let active = match jwt_algorithm.as_str() {
+            "HS512" => JwtKey {
+                algorithm: Algorithm::HS512,
+                encoding: Some(EncodingKey::from_secret(jwt_secret.as_bytes())),
+                decoding: DecodingKey::from_secret(jwt_secret.as_bytes()),
+                jwk: None,
+            },
+            "RS256" | "EdDSA" => {
+                let private_path = jwt_private_key.as_ref().context("JWT_PRIVATE_KEY is required for asymmetric signing")?;
+                let public_path = jwt_public_key.as_ref().context("JWT_PUBLIC_KEY is required for asymmetric signing")?;
+                let mut key = load_public_key(jwt_key_id, public_path)?;
+                let private_pem = std::fs::read(private_path)
+                    .with_context(|| format!("Unable to read JWT private key {}", private_path))?;
+                let encoding = match key.algorithm {
+                    Algorithm::RS256 if jwt_algorithm == "RS256" => EncodingKey::from_rsa_pem(&private_pem)?,
+                    Algorithm::EdDSA if jwt_algorithm == "EdDSA" => EncodingKey::from_ed_pem(&private_pem)?,
+                    _ => anyhow::bail!("JWT public key {} does not match JWT_ALGORITHM {}", public_path, jwt_algorithm),
+                };
+                key.encoding = Some(encoding);
+                key
+            },
+            other => anyhow::bail!("Unsupported JWT algorithm '{}', expected HS512, RS256 or EdDSA", other),
+        };
+        keys.insert(jwt_key_id.to_owned(), active);
+
+        // Retired keys are given as comma-separated lists of `kid:secret` and `kid:path` pairs
+        for (kid, secret) in parse_key_list(jwt_retired_keys)? {
+            let key = JwtKey {
+                algorithm: Algorithm::HS512,
+                encoding: None,
+                decoding: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            };
+            if keys.insert(kid.to_owned(), key).is_some() {
+                anyhow::bail!("Duplicate JWT key id '{}'", kid);
+            }
+        }
+        for (kid, path) in parse_key_list(jwt_retired_public_keys)? {
+            if keys.insert(kid.to_owned(), load_public_key(kid, path)?).is_some() {
+                anyhow::bail!("Duplicate JWT key id '{}'", kid);
+            }
+        }
+
+        Ok(Self {
+            active_kid: jwt_key_id.to_owned(),
+            keys,
+        })
+    }
+
+    pub fn active_kid(&self) -> &str { &self.active_kid }
+
+    pub fn algorithm(&self) -> Algorithm { self.keys[&self.active_kid].algorithm }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        self.keys[&self.active_kid].encoding.as_ref().expect("active key can sign")
+    }
+
+    // Tokens issued before key ids were introduced carry no `kid` and are checked against the active key
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<(Algorithm, &DecodingKey)> {
+        let kid = kid.unwrap_or(&self.active_kid);
+        self.keys.get(kid).map(|key| (key.algorithm, &key.decoding))
+    }
+
+    // Public keys that downstream services can use to verify our tokens, as a JWK Set
+    pub fn jwks(&self) -> Value {
+        let mut kids: Vec<&String> = self.keys.keys().collect();
+        kids.sort();
+        let keys: Vec<&Value> = kids.iter().filter_map(|kid| self.keys[*kid].jwk.as_ref()).collect();
+        json!({ "keys": keys })
+    }
+}
+
+
+fn parse_key_list(list: &Option<String>) -> anyhow::Result<Vec<(&str, &str)>> {
+    let mut result = Vec::new();
+    if let Some(list) = list {
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.find(':') {
+                Some(i) => result.push((&entry[..i], &entry[i + 1..])),
+                None => anyhow::bail!("Invalid JWT key '{}', expected 'kid:value'", entry),
+            }
+        }
+    }
+    Ok(result)
+}
+
+
+// Loads an RSA or Ed25519 public key from a PEM encoded SubjectPublicKeyInfo
+fn load_public_key(kid: &str, path: &str) -> anyhow::Result<JwtKey> {
+    let pem = std::fs::read(path).with_context(|| format!("Unable to read JWT public key {}", path))?;
+    let der = pem::parse(&pem).with_context(|| format!("Invalid PEM in JWT public key {}", path))?.contents;
+
+    let (algorithm_oid, public_key) = match simple_asn1::from_der(&der)?.as_slice() {
+        [ASN1Block::Sequence(_, spki)] => match spki.as_slice() {
+            [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, _, public_key)] => match algorithm.first() {
+                Some(ASN1Block::ObjectIdentifier(_, algorithm_oid)) => (algorithm_oid.clone(), public_key.clone()),
+                _ => anyhow::bail!("Missing key algorithm in JWT public key {}", path),
+            },
+            _ => anyhow::bail!("JWT public key {} is not a SubjectPublicKeyInfo", path),
+        },
+        _ => anyhow::bail!("JWT public key {} is not a SubjectPublicKeyInfo", path),
+    };
+
+    if algorithm_oid == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        let (modulus, exponent) = match simple_asn1::from_der(&public_key)?.as_slice() {
+            [ASN1Block::Sequence(_, parts)] => match parts.as_slice() {
+                [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => (n.to_bytes_be().1, e.to_bytes_be().1),
+                _ => anyhow::bail!("Invalid RSA public key {}", path),
+            },
+            _ => anyhow::bail!("Invalid RSA public key {}", path),
+        };
+        Ok(JwtKey {
+            algorithm: Algorithm::RS256,
+            encoding: None,
+            decoding: DecodingKey::from_rsa_raw_components(&modulus, &exponent),
+            jwk: Some(json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": kid,
+                "n": base64::encode_config(&modulus, base64::URL_SAFE_NO_PAD),
+                "e": base64::encode_config(&exponent, base64::URL_SAFE_NO_PAD),
+            })),
+        })
+    } else if algorithm_oid == oid!(1, 3, 101, 112) {
+        Ok(JwtKey {
+            algorithm: Algorithm::EdDSA,
+            encoding: None,
+            decoding: DecodingKey::from_ed_der(&public_key),
+            jwk: Some(json!({
+                "kty": "OKP",
+                "use": "sig",
+                "alg": "EdDSA",
+                "crv": "Ed25519",
+                "kid": kid,
+                "x": base64::encode_config(&public_key, base64::URL_SAFE_NO_PAD),
+            })),
+        })
+    } else {
+        anyhow::bail!("JWT public key {} is neither an RSA nor an Ed25519 key", path)
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use chrono::Utc;
+use lettre::Transport;
+
+use crate::environment::Args;
+
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Delivery backend for outgoing messages. Implementations are blocking and run on the blocking thread pool.
+pub trait MailSender: Send + Sync + std::fmt::Debug {
+    fn send(&self, from: &str, message: &Message) -> anyhow::Result<()>;
+}
+
+#[derive(Debug)]
+struct StdoutSender;
+
+impl MailSender for StdoutSender {
+    fn send(&self, from: &str, message: &Message) -> anyhow::Result<()> {
+        println!("[mailer] From: {}\nTo: {}\nSubject: {}\n\n{}\n", from, message.to, message.subject, message.body);
+        Ok(())
+    }
+}
+
+// Writes every message as a JSON file to the outbox directory, for local development and tests
+#[derive(Debug)]
+struct FileSender {
+    outbox_dir: String,
+}
+
+impl MailSender for FileSender {
+    fn send(&self, from: &str, message: &Message) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.outbox_dir)?;
+        let file_name = format!("{}-{}.json", Utc::now().format("%Y%m%d%H%M%S%3f"), uuid::Uuid::new_v4());
+        let path = std::path::Path::new(&self.outbox_dir).join(file_name);
+        let content = serde_json::json!({ "from": from, "to": message.to, "subject": message.subject, "body": message.body });
+        std::fs::write(&path, serde_json::to_vec_pretty(&content)?)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct SmtpSender {
+    host: String,
+    port: Option<u16>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl MailSender for SmtpSender {
+    fn send(&self, from: &str, message: &Message) -> anyhow::Result<()> {
+        let email = lettre_email::EmailBuilder::new()
+            .from(from)
+            .to(message.to.as_str())
+            .subject(message.subject.as_str())
+            .text(message.body.as_str())
+            .build()?;
+
+        let credentials = match (&self.username, &self.password) {
+            (Some(username), Some(password)) => Some(lettre::smtp::authentication::Credentials::new(username.to_owned(), password.to_owned())),
+            _ => None,
+        };
+        // Without an explicit port, TLS on the submissions port is used. On an explicit port, STARTTLS is required whenever
+        // credentials are sent, so only local relays without credentials (such as MailHog) are spoken to in cleartext.
+        let mut client = match self.port {
+            Some(port) if credentials.is_some() => {
+                let tls = lettre::ClientTlsParameters::new(self.host.to_owned(), native_tls::TlsConnector::new()?);
+                lettre::SmtpClient::new((self.host.as_str(), port), lettre::ClientSecurity::Required(tls))?
+            },
+            Some(port) => lettre::SmtpClient::new((self.host.as_str(), port), lettre::ClientSecurity::None)?,
+            None => lettre::SmtpClient::new_simple(&self.host)?,
+        };
+        if let Some(credentials) = credentials {
+            client = client.credentials(credentials);
+        }
+        client.transport().send(email.into())?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Mailer {
+    from: String,
+    sender: Arc<dyn MailSender>,
+}
+
+impl Mailer {
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let Args {
+            mailer,
+            mail_from,
+            mail_outbox_dir,
+            smtp_host,
+            smtp_port,
+            smtp_username,
+            smtp_password,
+            ..
+        } = args;
+
+        let sender: Arc<dyn MailSender> = match mailer.as_str() {
+            "stdout" => Arc::new(StdoutSender),
+            "file" => Arc::new(FileSender { outbox_dir: mail_outbox_dir.to_owned() }),
+            "smtp" => Arc::new(SmtpSender {
+                host: smtp_host.to_owned().context("SMTP_HOST is required for the smtp mailer")?,
+                port: smtp_port.to_owned(),
+                username: smtp_username.to_owned(),
+                password: smtp_password.to_owned(),
+            }),
+            other => anyhow::bail!("Unsupported mailer '{}', expected stdout, file or smtp", other),
+        };
+        Ok(Self {
+            from: mail_from.to_owned(),
+            sender,
+        })
+    }
+
+    // Delivers the message in the background, so that callers do not wait on (or leak timing of) the mail server
+    pub fn send(&self, message: Message) {
+        let sender = self.sender.clone();
+        let from = self.from.clone();
+        tokio::task::spawn_blocking(move || {
+            match sender.send(&from, &message) {
+                Ok(()) => println!("[mailer] Sent '{}' to {}", &message.subject, &message.to),
+                Err(_e) => println!("ERROR [mailer] Unable to send '{}' to {}: {:?}", &message.subject, &message.to, _e),
+            }
+        });
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counters kept by this instance since it started
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    password_rehashes: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    // Counts a password hash upgraded to the current argon parameters
+    pub fn record_password_rehash(&self) {
+        self.password_rehashes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn password_rehashes(&self) -> u64 {
+        self.password_rehashes.load(Ordering::Relaxed)
+    }
+
+    // Prometheus text exposition format
+    pub fn render(&self) -> String {
+        format!(
+            "# HELP password_rehashes_total Password hashes upgraded to the current argon parameters on login\n\
+             # TYPE password_rehashes_total counter\n\
+             password_rehashes_total {}\n",
+            self.password_rehashes()
+        )
+    }
+}
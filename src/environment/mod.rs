@@ -5,14 +5,37 @@ use clap::Clap;
 use mongodb::{options::ClientOptions, Client, Database};
 use warp::Filter;
 
+use crate::auth::revocation::RevocationCache;
+use crate::roles::service::RoleCache;
 use argon::Argon;
+use jwt::JwtKeys;
+use mailer::Mailer;
+use metrics::Metrics;
+use password_policy::PasswordPolicy;
+use secret_box::SecretBox;
+use storage::Storage;
 mod argon;
+mod jwt;
+pub mod mailer;
+mod metrics;
+mod password_policy;
+mod secret_box;
+mod storage;
 
 #[derive(Clone, Debug)]
 pub struct Environment {
     db_pool: Client,
     config: Args,
     argon: Argon,
+    jwt_keys: JwtKeys,
+    revocations: RevocationCache,
+    roles: RoleCache,
+    mailer: Mailer,
+    password_policy: PasswordPolicy,
+    secret_box: SecretBox,
+    http: reqwest::Client,
+    metrics: Metrics,
+    storage: Storage,
 }
 
 #[derive(Clone, Clap, Debug)]
@@ -31,17 +54,146 @@ pub struct Args {
     #[clap(required = true, long, env)]
     db_name: String,
 
+    #[clap(default_value = "HS512", long, env)]
+    jwt_algorithm: String,
     #[clap(required = true, long, env)]
     jwt_secret: String,
+    #[clap(default_value = "default", long, env)]
+    jwt_key_id: String,
+    #[clap(long, env)]
+    jwt_retired_keys: Option<String>,
+    #[clap(long, env)]
+    jwt_private_key: Option<String>,
+    #[clap(long, env)]
+    jwt_public_key: Option<String>,
+    #[clap(long, env)]
+    jwt_retired_public_keys: Option<String>,
+    #[clap(default_value = "900", long, env)]
+    pub access_token_ttl: i64,
+    #[clap(default_value = "1209600", long, env)]
+    pub refresh_token_ttl: i64,
+    // Lifetime of the tokens issued to admins impersonating a user
+    #[clap(default_value = "1800", long, env)]
+    pub impersonation_ttl: i64,
+    #[clap(default_value = "3600", long, env)]
+    pub password_reset_ttl: i64,
+    #[clap(default_value = "86400", long, env)]
+    pub email_verification_ttl: i64,
+    // Refuse logins of accounts that have not confirmed their email address
+    #[clap(default_value = "false", long, env, parse(try_from_str))]
+    pub require_verified_email: bool,
+    // Send the tokens of browser logins as HttpOnly cookies instead of in the response body
+    #[clap(default_value = "false", long, env, parse(try_from_str))]
+    pub cookie_sessions: bool,
+    // Only disable for local development over plain HTTP
+    #[clap(default_value = "true", long, env, parse(try_from_str))]
+    pub cookie_secure: bool,
+    // Only allow registering with an invitation code, see /api/invitations
+    #[clap(default_value = "false", long, env, parse(try_from_str))]
+    pub invite_only_registration: bool,
+    // Lifetime of invitations created without an expiry
+    #[clap(default_value = "604800", long, env)]
+    pub invitation_ttl: i64,
+    // Failed logins before an account (or client IP) is locked out for LOGIN_LOCKOUT_DURATION seconds
+    #[clap(default_value = "10", long, env)]
+    pub login_lockout_threshold: i64,
+    #[clap(default_value = "100", long, env)]
+    pub login_ip_lockout_threshold: i64,
+    #[clap(default_value = "900", long, env)]
+    pub login_lockout_duration: i64,
+    // Take the client IP from X-Forwarded-For, only when running behind a trusted reverse proxy
+    #[clap(default_value = "false", long, env, parse(try_from_str))]
+    pub trust_forwarded_for: bool,
+    // Issuer name shown by authenticator apps
+    #[clap(default_value = "rust-crud-nosql", long, env)]
+    pub mfa_issuer: String,
+
+    // Base URL of the web application, used to build links sent by email
+    #[clap(default_value = "http://localhost:8000", long, env)]
+    pub app_url: String,
+    // OpenID Connect provider used for single sign-on, disabled unless both the issuer and client id are set
+    #[clap(long, env)]
+    pub oidc_issuer_url: Option<String>,
+    #[clap(long, env)]
+    pub oidc_client_id: Option<String>,
+    #[clap(long, env)]
+    pub oidc_client_secret: Option<String>,
+    // Defaults to APP_URL/api/auth/oidc/callback
+    #[clap(long, env)]
+    pub oidc_redirect_url: Option<String>,
+    #[clap(default_value = "openid email profile", long, env)]
+    pub oidc_scopes: String,
+    // stdout or file, which expose the reset and verification links, are only meant for development
+    #[clap(required = true, long, env)]
+    mailer: String,
+    #[clap(default_value = "no-reply@localhost", long, env)]
+    mail_from: String,
+    #[clap(default_value = "outbox", long, env)]
+    mail_outbox_dir: String,
+    #[clap(long, env)]
+    smtp_host: Option<String>,
+    #[clap(long, env)]
+    smtp_port: Option<u16>,
+    #[clap(long, env)]
+    smtp_username: Option<String>,
+    #[clap(long, env)]
+    smtp_password: Option<String>,
+    // Where uploaded files such as avatars are kept. Only local, under STORAGE_DIR, is supported.
+    #[clap(default_value = "local", long, env)]
+    storage: String,
+    #[clap(default_value = "uploads", long, env)]
+    storage_dir: String,
+    #[clap(default_value = "10", long, env)]
+    password_min_length: usize,
+    #[clap(default_value = "128", long, env)]
+    password_max_length: usize,
+    // Out of lowercase letters, uppercase letters, digits and symbols
+    #[clap(default_value = "2", long, env)]
+    password_min_character_classes: usize,
+    // SHA-1 hashes of breached passwords that cannot be used
+    #[clap(long, env)]
+    breached_passwords_file: Option<String>,
     #[clap(required = true, long, env)]
     argon_secret: String,
     #[clap(long, env)]
     argon_iterations: Option<u32>,
     #[clap(long, env)]
     argon_memory_size: Option<u32>,
+    // Encrypts the secrets kept in the database, such as TOTP secrets
+    #[clap(required = true, long, env)]
+    secrets_key: String,
 
     #[clap(default_value = "0.0.0.0:8080", env)]
     pub host: SocketAddr,
+
+    // Runs a maintenance task instead of the server
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Clap, Debug)]
+#[clap(rename_all = "kebab-case")]
+pub enum Command {
+    // Imports users from a CSV or JSON Lines file and prints the report
+    ImportUsers {
+        file: String,
+        #[clap(default_value = "csv", long)]
+        format: String,
+        // skip or update the users that already exist
+        #[clap(default_value = "skip", long)]
+        mode: String,
+    },
+    // Exports every user, without password hashes, to a CSV or JSON Lines file
+    ExportUsers {
+        file: String,
+        #[clap(default_value = "csv", long)]
+        format: String,
+    },
+    // Lists the accounts sharing an email address, and merges them with --merge
+    MergeDuplicates {
+        #[clap(long)]
+        merge: bool,
+    },
 }
 
 impl Environment {
@@ -60,10 +212,25 @@ impl Environment {
         let db_pool = Client::with_options(db_config)?;
 
         let argon = Argon::new(&args);
+        let jwt_keys = JwtKeys::new(&args)?;
+        let mailer = Mailer::new(&args)?;
+        let password_policy = PasswordPolicy::new(&args)?;
+        let secret_box = SecretBox::new(&args)?;
+        let storage = Storage::new(&args)?;
+        let http = reqwest::Client::builder().timeout(std::time::Duration::new(10, 0)).build()?;
         Ok(Self {
             db_pool,
             config: args,
             argon,
+            jwt_keys,
+            revocations: RevocationCache::default(),
+            roles: RoleCache::default(),
+            mailer,
+            password_policy,
+            secret_box,
+            http,
+            metrics: Metrics::default(),
+            storage,
         })
     }
 
@@ -75,6 +242,24 @@ impl Environment {
     pub fn config(&self) -> &Args { &self.config }
 
     pub fn argon(&self) -> &Argon { &self.argon }
+
+    pub fn jwt_keys(&self) -> &JwtKeys { &self.jwt_keys }
+
+    pub fn revocations(&self) -> &RevocationCache { &self.revocations }
+
+    pub fn roles(&self) -> &RoleCache { &self.roles }
+
+    pub fn mailer(&self) -> &Mailer { &self.mailer }
+
+    pub fn password_policy(&self) -> &PasswordPolicy { &self.password_policy }
+
+    pub fn secret_box(&self) -> &SecretBox { &self.secret_box }
+
+    pub fn http(&self) -> &reqwest::Client { &self.http }
+
+    pub fn metrics(&self) -> &Metrics { &self.metrics }
+
+    pub fn storage(&self) -> &Storage { &self.storage }
 }
 
 pub fn with_env(env: Environment) -> impl Filter<Extract=(Environment, ), Error=Infallible> + Clone {
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::environment::Args;
+use crate::error::{AppError, FieldError};
+
+// Length of the SHA-1 prefix that breached hashes are grouped by, as in the k-anonymity range API of Have I Been Pwned
+const HASH_PREFIX_LENGTH: usize = 5;
+
+// Rules every new password must follow, shared by registration, user creation and password changes
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    min_character_classes: usize,
+    // Breached SHA-1 hashes: suffixes (sorted) by prefix
+    breached: HashMap<String, Vec<String>>,
+}
+
+impl PasswordPolicy {
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let Args {
+            password_min_length,
+            password_max_length,
+            password_min_character_classes,
+            breached_passwords_file,
+            ..
+        } = args;
+
+        if password_min_length > password_max_length {
+            anyhow::bail!("PASSWORD_MIN_LENGTH cannot be greater than PASSWORD_MAX_LENGTH");
+        }
+        let breached = match breached_passwords_file {
+            Some(path) => load_breached_hashes(path)?,
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            min_length: *password_min_length,
+            max_length: *password_max_length,
+            min_character_classes: *password_min_character_classes,
+            breached,
+        })
+    }
+
+    // Reports every rule the password breaks at once, so clients can show them together
+    pub fn validate(&self, password: &str, email: &str, name: &str) -> Result<(), AppError> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            errors.push(FieldError::new("password", "too_short", &format!("must be at least {} characters long", self.min_length)));
+        }
+        // Long inputs are refused rather than truncated, as hashing cost grows with their length
+        if length > self.max_length {
+            errors.push(FieldError::new("password", "too_long", &format!("must be at most {} characters long", self.max_length)));
+        }
+        if character_classes(password) < self.min_character_classes {
+            errors.push(FieldError::new("password", "too_simple", &format!(
+                "must contain at least {} of lowercase letters, uppercase letters, digits and symbols", self.min_character_classes)));
+        }
+        if is_personal(password, email, name) {
+            errors.push(FieldError::new("password", "personal", "must not be your email address or name"));
+        }
+        if self.is_breached(password) {
+            errors.push(FieldError::new("password", "breached", "has appeared in a data breach, choose another one"));
+        }
+        if !errors.is_empty() {
+            return Err(AppError::ValidationError(errors));
+        }
+        Ok(())
+    }
+
+    fn is_breached(&self, password: &str) -> bool {
+        let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+        self.breached.get(prefix).map(|suffixes| suffixes.binary_search(&suffix.to_owned()).is_ok()).unwrap_or(false)
+    }
+}
+
+
+fn character_classes(password: &str) -> usize {
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    classes.iter().filter(|present| **present).count()
+}
+
+
+fn is_personal(password: &str, email: &str, name: &str) -> bool {
+    let password = password.trim().to_lowercase();
+    let email = email.trim().to_lowercase();
+    let local_part = email.split('@').next().unwrap_or("");
+    [email.as_str(), local_part, name.trim().to_lowercase().as_str()].iter()
+        .any(|value| !value.is_empty() && password == *value)
+}
+
+
+// Reads a file with one uppercase or lowercase SHA-1 hash per line, optionally followed by `:count` as in the
+// Have I Been Pwned downloads
+fn load_breached_hashes(path: &str) -> anyhow::Result<HashMap<String, Vec<String>>> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Unable to read breached passwords file {}", path))?;
+    let mut breached: HashMap<String, Vec<String>> = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        let hash = line.split(':').next().unwrap_or("").trim().to_uppercase();
+        if hash.is_empty() {
+            continue;
+        }
+        if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("Invalid SHA-1 hash on line {} of {}", number + 1, path);
+        }
+        let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+        breached.entry(prefix.to_owned()).or_default().push(suffix.to_owned());
+    }
+    for suffixes in breached.values_mut() {
+        suffixes.sort();
+        suffixes.dedup();
+    }
+    println!("Loaded {} breached password hashes", breached.values().map(Vec::len).sum::<usize>());
+    Ok(breached)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(breached: HashMap<String, Vec<String>>) -> PasswordPolicy {
+        PasswordPolicy { min_length: 8, max_length: 64, min_character_classes: 2, breached }
+    }
+
+    fn error_codes(result: Result<(), AppError>) -> Vec<String> {
+        match result {
+            Ok(()) => Vec::new(),
+            Err(AppError::ValidationError(errors)) => errors.into_iter().map(|e| e.code).collect(),
+            Err(other) => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_password() {
+        assert!(policy(HashMap::new()).validate("correct-Horse", "jane@example.com", "Jane").is_ok());
+    }
+
+    #[test]
+    fn reports_every_broken_rule() {
+        assert_eq!(error_codes(policy(HashMap::new()).validate("short", "jane@example.com", "Jane")), vec!["too_short", "too_simple"]);
+        assert_eq!(error_codes(policy(HashMap::new()).validate(&"a1".repeat(33), "jane@example.com", "Jane")), vec!["too_long"]);
+    }
+
+    #[test]
+    fn counts_length_in_characters() {
+        assert!(policy(HashMap::new()).validate("pässwörd1", "jane@example.com", "Jane").is_ok());
+        assert_eq!(error_codes(policy(HashMap::new()).validate("äöü1äö", "jane@example.com", "Jane")), vec!["too_short"]);
+    }
+
+    #[test]
+    fn refuses_the_email_or_name() {
+        let policy = policy(HashMap::new());
+        assert_eq!(error_codes(policy.validate("Jane@Example.com", "jane@example.com", "Jane")), vec!["personal"]);
+        assert_eq!(error_codes(policy.validate("janedoe1", "janedoe1@example.com", "Jane")), vec!["personal"]);
+        assert_eq!(error_codes(policy.validate("Jane Doe 1", "jane@example.com", "jane doe 1")), vec!["personal"]);
+    }
+
+    #[test]
+    fn counts_character_classes() {
+        assert_eq!(character_classes("abc"), 1);
+        assert_eq!(character_classes("abcDEF"), 2);
+        assert_eq!(character_classes("abcDEF123"), 3);
+        assert_eq!(character_classes("abcDEF123!"), 4);
+    }
+
+    #[test]
+    fn finds_breached_passwords_from_file() {
+        // SHA-1 of "password1", in lowercase with a count, and of "P@ssw0rd"
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", std::process::id()));
+        std::fs::write(&path, "e38ad214943daad1d64c102faec29de4afe9da3d:2413945\n\n21BD12DC183F740EE76F27B78EB39C8AD972A757\n").unwrap();
+        let breached = load_breached_hashes(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let policy = policy(breached.unwrap());
+        assert!(policy.is_breached("password1"));
+        assert!(policy.is_breached("P@ssw0rd"));
+        assert!(!policy.is_breached("password2"));
+        assert_eq!(error_codes(policy.validate("password1", "jane@example.com", "Jane")), vec!["breached"]);
+    }
+
+    #[test]
+    fn refuses_invalid_breached_files() {
+        let path = std::env::temp_dir().join(format!("breached-invalid-{}.txt", std::process::id()));
+        std::fs::write(&path, "not a hash\n").unwrap();
+        let breached = load_breached_hashes(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(breached.is_err());
+    }
+}
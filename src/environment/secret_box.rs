@@ -0,0 +1,85 @@
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::Result;
+use crate::environment::Args;
+use crate::error::AppError;
+
+// Versions the format of the sealed values
+const PREFIX: &str = "enc:v1:";
+const NONCE_LENGTH: usize = 12;
+
+// Encrypts the secrets kept in the database (TOTP secrets) with AES-256-GCM under the server key, and digests the values
+// that are only looked up (email addresses of erased users) with HMAC-SHA256 under a key of its own
+#[derive(Clone, Debug)]
+pub struct SecretBox {
+    key: [u8; 32],
+    digest_key: [u8; 32],
+}
+
+impl SecretBox {
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let Args {
+            secrets_key,
+            ..
+        } = args;
+
+        if secrets_key.len() < 16 {
+            anyhow::bail!("SECRETS_KEY must be at least 16 characters long");
+        }
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&Sha256::digest(secrets_key.as_bytes()));
+        let mut digest_key = [0u8; 32];
+        digest_key.copy_from_slice(&Sha256::digest(format!("digest:{}", secrets_key).as_bytes()));
+        Ok(Self { key, digest_key })
+    }
+
+    // Unlike a plain hash, the digest cannot be matched against a list of candidate values without the server key
+    pub fn digest(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.digest_key).expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let cipher = Aes256Gcm::new(&Key::from(self.key));
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher.encrypt(&Nonce::from(nonce), plaintext.as_bytes()).map_err(|_e| {
+            println!("ERROR [SecretBox::encrypt] {:?}", _e);
+            return AppError::DataError;
+        })?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!("{}{}", PREFIX, base64::encode(sealed)))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let encoded = value.strip_prefix(PREFIX).ok_or_else(|| {
+            println!("ERROR [SecretBox::decrypt] value is not sealed");
+            return AppError::DataError;
+        })?;
+        let sealed = base64::decode(encoded).map_err(|_e| {
+            println!("ERROR [SecretBox::decrypt] {:?}", _e);
+            return AppError::DataError;
+        })?;
+        if sealed.len() < NONCE_LENGTH {
+            println!("ERROR [SecretBox::decrypt] sealed value too short");
+            return Err(AppError::DataError);
+        }
+        let mut nonce = [0u8; NONCE_LENGTH];
+        nonce.copy_from_slice(&sealed[..NONCE_LENGTH]);
+        let cipher = Aes256Gcm::new(&Key::from(self.key));
+        let plaintext = cipher.decrypt(&Nonce::from(nonce), &sealed[NONCE_LENGTH..]).map_err(|_e| {
+            println!("ERROR [SecretBox::decrypt] {:?}", _e);
+            return AppError::DataError;
+        })?;
+        String::from_utf8(plaintext).map_err(|_e| {
+            println!("ERROR [SecretBox::decrypt] {:?}", _e);
+            return AppError::DataError;
+        })
+    }
+}
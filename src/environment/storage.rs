@@ -0,0 +1,117 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::Result;
+use crate::environment::Args;
+use crate::error::AppError;
+
+// Backend keeping uploaded files by key, such as `avatars/{user_id}/small.png`.
+// Implementations are blocking and run on the blocking thread pool.
+pub trait FileStore: Send + Sync + std::fmt::Debug {
+    fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()>;
+    fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+// Keeps files under a directory of the local filesystem, one file per key
+#[derive(Debug)]
+struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    // Keys are relative paths that cannot leave the root directory
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            anyhow::bail!("Invalid storage key '{}'", key);
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl FileStore for LocalStore {
+    fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Written aside and renamed, so that readers never get a partial file
+        let partial = path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+        std::fs::write(&partial, data)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path(key)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match std::fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Storage {
+    store: Arc<dyn FileStore>,
+}
+
+impl Storage {
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let Args {
+            storage,
+            storage_dir,
+            ..
+        } = args;
+
+        let store: Arc<dyn FileStore> = match storage.as_str() {
+            "local" => Arc::new(LocalStore { root: PathBuf::from(storage_dir) }),
+            other => anyhow::bail!("Unsupported storage '{}', expected local", other),
+        };
+        Ok(Self { store })
+    }
+
+    pub async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let store = self.store.clone();
+        let key = key.to_owned();
+        tokio::task::spawn_blocking(move || store.put(&key, &data)).await.map_err(|_e| {
+            println!("ERROR [storage::put] {:?}", _e);
+            return AppError::DataError;
+        })?.map_err(|_e| {
+            println!("ERROR [storage::put] {:?}", _e);
+            return AppError::DataError;
+        })
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let store = self.store.clone();
+        let key = key.to_owned();
+        tokio::task::spawn_blocking(move || store.get(&key)).await.map_err(|_e| {
+            println!("ERROR [storage::get] {:?}", _e);
+            return AppError::DataError;
+        })?.map_err(|_e| {
+            println!("ERROR [storage::get] {:?}", _e);
+            return AppError::DataError;
+        })
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        let store = self.store.clone();
+        let key = key.to_owned();
+        tokio::task::spawn_blocking(move || store.delete(&key)).await.map_err(|_e| {
+            println!("ERROR [storage::delete] {:?}", _e);
+            return AppError::DataError;
+        })?.map_err(|_e| {
+            println!("ERROR [storage::delete] {:?}", _e);
+            return AppError::DataError;
+        })
+    }
+}
@@ -14,7 +14,31 @@ pub async fn error_handler(err: warp::reject::Rejection) -> std::result::Result<
             AppError::ArticleNotFoundError => (StatusCode::BAD_REQUEST, e.to_string()),
             AppError::WrongCredentialsError => (StatusCode::FORBIDDEN, e.to_string()),
             AppError::NoPermissionError => (StatusCode::UNAUTHORIZED, e.to_string()),
+            AppError::CsrfTokenError => (StatusCode::FORBIDDEN, e.to_string()),
+            AppError::EmailNotVerifiedError => (StatusCode::FORBIDDEN, e.to_string()),
+            AppError::AccountDisabledError => (StatusCode::FORBIDDEN, e.to_string()),
+            AppError::TooManyLoginAttemptsError => (StatusCode::TOO_MANY_REQUESTS, e.to_string()),
+            AppError::AccountLockedError => (StatusCode::LOCKED, e.to_string()),
+            AppError::MfaCodeError => (StatusCode::FORBIDDEN, e.to_string()),
+            AppError::MfaAlreadyEnabledError => (StatusCode::CONFLICT, e.to_string()),
             AppError::JWTTokenError => (StatusCode::UNAUTHORIZED, e.to_string()),
+            AppError::RefreshTokenError => (StatusCode::UNAUTHORIZED, e.to_string()),
+            AppError::InvalidApiKeyError => (StatusCode::UNAUTHORIZED, e.to_string()),
+            AppError::ApiKeyNotFoundError => (StatusCode::NOT_FOUND, e.to_string()),
+            AppError::InvitationRequiredError => (StatusCode::FORBIDDEN, e.to_string()),
+            AppError::InvalidInvitationError => (StatusCode::FORBIDDEN, e.to_string()),
+            AppError::InvitationNotFoundError => (StatusCode::NOT_FOUND, e.to_string()),
+            AppError::SessionNotFoundError => (StatusCode::NOT_FOUND, e.to_string()),
+            AppError::LastAdminError => (StatusCode::CONFLICT, e.to_string()),
+            AppError::EmailExistsError => (StatusCode::CONFLICT, e.to_string()),
+            AppError::AvatarNotFoundError => (StatusCode::NOT_FOUND, e.to_string()),
+            AppError::RoleNotFoundError => (StatusCode::NOT_FOUND, e.to_string()),
+            AppError::RoleExistsError => (StatusCode::CONFLICT, e.to_string()),
+            AppError::RoleInUseError => (StatusCode::CONFLICT, e.to_string()),
+            AppError::InvalidUserTokenError => (StatusCode::BAD_REQUEST, e.to_string()),
+            AppError::OidcError => (StatusCode::UNAUTHORIZED, e.to_string()),
+            AppError::OidcNotConfiguredError => (StatusCode::NOT_FOUND, e.to_string()),
+            AppError::ValidationError(_) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
             AppError::JWTTokenCreationError => (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error".to_string()),
             _ => (StatusCode::BAD_REQUEST, e.to_string()),
         }
@@ -37,9 +61,14 @@ pub async fn error_handler(err: warp::reject::Rejection) -> std::result::Result<
     };
 
 
+    let errors = match err.find::<AppError>() {
+        Some(AppError::ValidationError(errors)) => errors.clone(),
+        _ => Vec::new(),
+    };
     let json = warp::reply::json(&ErrorResponse {
         status: code.to_string(),
         message,
+        errors,
     });
 
     Ok(warp::reply::with_status(json, code))
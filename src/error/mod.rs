@@ -16,10 +16,47 @@ pub enum AppError {
     JWTTokenCreationError,
     #[error("no auth header")]
     NoAuthHeaderError,
+    #[error("refresh token not valid")]
+    RefreshTokenError,
+    #[error("token is invalid or has expired")]
+    InvalidUserTokenError,
+    #[error("api key not valid")]
+    InvalidApiKeyError,
+    #[error("invalid api key name, scopes or expiry")]
+    InvalidApiKeyRequestError,
     #[error("invalid auth header")]
     InvalidAuthHeaderError,
+    #[error("missing or invalid csrf token")]
+    CsrfTokenError,
     #[error("no permission")]
     NoPermissionError,
+    #[error("email address not verified")]
+    EmailNotVerifiedError,
+    #[error("account disabled")]
+    AccountDisabledError,
+    #[error("too many failed login attempts, try again later")]
+    TooManyLoginAttemptsError,
+    #[error("account temporarily locked after too many failed login attempts")]
+    AccountLockedError,
+    #[error("invalid two-factor code")]
+    MfaCodeError,
+    #[error("two-factor authentication is already enabled")]
+    MfaAlreadyEnabledError,
+
+    #[error("registration requires an invitation")]
+    InvitationRequiredError,
+    #[error("invitation is invalid, used up or has expired")]
+    InvalidInvitationError,
+    #[error("invalid invitation email, uses or expiry")]
+    InvalidInvitationRequestError,
+
+    #[error("single sign-on failed")]
+    OidcError,
+    #[error("single sign-on is not configured")]
+    OidcNotConfiguredError,
+
+    #[error("validation failed")]
+    ValidationError(Vec<FieldError>),
 
     #[error("data error")]
     DataError,
@@ -31,8 +68,30 @@ pub enum AppError {
 
     #[error("user not found")]
     UserNotFound,
+    #[error("the last admin cannot be removed")]
+    LastAdminError,
+    #[error("email address already registered")]
+    EmailExistsError,
+    #[error("avatar not found")]
+    AvatarNotFoundError,
     #[error("article not found")]
     ArticleNotFoundError,
+    #[error("api key not found")]
+    ApiKeyNotFoundError,
+    #[error("invitation not found")]
+    InvitationNotFoundError,
+    #[error("session not found")]
+    SessionNotFoundError,
+    #[error("role not found")]
+    RoleNotFoundError,
+    #[error("invalid role name or permissions")]
+    InvalidRoleError,
+    #[error("role already exists")]
+    RoleExistsError,
+    #[error("role is assigned to users")]
+    RoleInUseError,
+    #[error("built-in role cannot be changed")]
+    BuiltInRoleError,
 }
 impl warp::reject::Reject for AppError {}
 
@@ -40,6 +99,26 @@ impl warp::reject::Reject for AppError {}
 struct ErrorResponse {
     message: String,
     status: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+// Rule broken by a field of the request, reported with ValidationError
+#[derive(Clone, Serialize, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &str, message: &str) -> FieldError {
+        FieldError {
+            field: field.to_owned(),
+            code: code.to_owned(),
+            message: message.to_owned(),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -0,0 +1,46 @@
+use warp::Reply;
+use serde_json::json;
+use warp::reject;
+use chrono::Utc;
+
+use crate::{roles, WebResult};
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
+use crate::error::AppError;
+use crate::invitations::models::{InvitationCreateRequest, InvitationCreateResponse};
+use crate::invitations::service;
+
+pub async fn get_invitations_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_invitations(_env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+// Creates an invitation. An email-bound invitation can be used once, by that address only.
+// The code is part of this response only.
+pub async fn create_invitation_handler(_req: InvitationCreateRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let email = _req.email.as_deref().map(str::trim).filter(|email| !email.is_empty());
+    let max_uses = match (email, _req.max_uses) {
+        (Some(_), None) | (Some(_), Some(1)) => 1,
+        (Some(_), Some(_)) => return Err(reject::custom(AppError::InvalidInvitationRequestError)),
+        (None, Some(max_uses)) if max_uses > 0 => max_uses,
+        (None, _) => return Err(reject::custom(AppError::InvalidInvitationRequestError)),
+    };
+    let expires_at = _req.expires_at.unwrap_or_else(|| Utc::now() + chrono::Duration::seconds(_env.config().invitation_ttl));
+    if expires_at <= Utc::now() {
+        return Err(reject::custom(AppError::InvalidInvitationRequestError));
+    }
+    // Only roles that are built in or defined in the roles collection can be given, within the inviter's permissions
+    let role = _req.role.unwrap_or(Role::User);
+    roles::service::get_role(&_env, &role.to_string()).await.map_err(reject::custom)?;
+    roles::service::ensure_role_within(&_env, &_user, &role).await.map_err(reject::custom)?;
+
+    let (invitation, code) = service::create_invitation(email, &role, max_uses, expires_at, &_user.id, _env.db()).await.map_err(reject::custom)?;
+    println!("[create_invitation_handler] User {} created invitation {} ({})", _user, &invitation.id, &role);
+    Ok(warp::reply::json(&InvitationCreateResponse { invitation, code }))
+}
+
+pub async fn delete_invitation_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    service::delete_invitation(&_id, _env.db()).await.map_err(reject::custom)?;
+    println!("[delete_invitation_handler] User {} deleted invitation {}", _user, &_id);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Invitation deleted"})))
+}
@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::models::Role;
+
+// Invitation to register, either bound to one email address or usable `max_uses` times by anyone with the code.
+// Only the hash of the code is stored.
+#[derive(Clone, Serialize, Debug)]
+pub struct Invitation {
+    pub id: String,
+    pub email: Option<String>,
+    pub role: Role,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub expires_at: DateTime<Utc>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct InvitationCreateRequest {
+    pub email: Option<String>,
+    pub role: Option<Role>,
+    pub max_uses: Option<i64>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// The code itself is only returned once, when the invitation is created
+#[derive(Serialize)]
+pub struct InvitationCreateResponse {
+    #[serde(flatten)]
+    pub invitation: Invitation,
+    pub code: String,
+}
@@ -0,0 +1,31 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::environment::Environment;
+use crate::invitations::handlers;
+use crate::roles::USERS_MANAGE;
+
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    let get_invitations_route = warp::get().and(warp::path!("api" / "invitations")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::get_invitations_handler));
+
+    let create_invitation_route = warp::post().and(warp::path!("api" / "invitations")
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::create_invitation_handler));
+
+    let delete_invitation_route = warp::delete().and(warp::path!("api" / "invitations" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::delete_invitation_handler));
+
+    let routes = get_invitations_route
+        .or(create_invitation_route)
+        .or(delete_invitation_route);
+
+    routes.boxed()
+}
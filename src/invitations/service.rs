@@ -0,0 +1,124 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::Database;
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use tokio::stream::StreamExt;
+
+use crate::Result;
+use crate::auth::models::Role;
+use crate::auth::utils::{generate_token, hash_token};
+use crate::error::AppError;
+use crate::invitations::models::Invitation;
+use crate::invitations::utils::doc_to_invitation;
+use crate::users::utils::normalize_email;
+
+
+// Expired invitations are removed by MongoDB itself
+pub async fn create_indexes(_db: Database) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "invitations",
+        "indexes": [
+            { "key": { "code_hash": 1 }, "name": "code_hash", "unique": true },
+            { "key": { "expires_at": 1 }, "name": "expires_at_ttl", "expireAfterSeconds": 0 },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [invitations::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Stores a new invitation and returns it along with its code, which is never stored
+pub async fn create_invitation(email: Option<&str>, role: &Role, max_uses: i64, expires_at: DateTime<Utc>, created_by: &str, _db: Database) -> Result<(Invitation, String)> {
+    let code = generate_token();
+    let mut doc = doc! {
+        "code_hash": hash_token(&code),
+        "role": role.to_string(),
+        "max_uses": max_uses,
+        "uses": 0i64,
+        "expires_at": expires_at,
+        "created_by": created_by,
+        "created_at": Utc::now(),
+    };
+    if let Some(email) = email {
+        doc.insert("email", normalize_email(email));
+    }
+    let _result = _db.collection("invitations").insert_one(doc.clone(), None).await.map_err(|_e| {
+        println!("ERROR [create_invitation] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    doc.insert("_id", _result.inserted_id);
+    Ok((doc_to_invitation(&doc)?, code))
+}
+
+
+pub async fn get_invitations(_db: Database) -> Result<Vec<Invitation>> {
+    let mut _cursor = _db.collection("invitations").find(None, None).await.map_err(|_e| {
+        println!("ERROR [get_invitations] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<Invitation> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        result.push(doc_to_invitation(&doc?)?);
+    }
+    Ok(result)
+}
+
+
+pub async fn delete_invitation(id: &str, _db: Database) -> Result<()> {
+    let oid = ObjectId::with_string(id).map_err(|_| AppError::InvitationNotFoundError)?;
+    let _result = _db.collection("invitations").delete_one(doc! { "_id": oid }, None).await.map_err(|_e| {
+        println!("ERROR [delete_invitation] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.deleted_count == 0 {
+        return Err(AppError::InvitationNotFoundError);
+    }
+    Ok(())
+}
+
+
+// Counts a use of the invitation with the given code, if it is unexpired, has uses left and is not bound to
+// another email address. The check and the count happen in one update so concurrent registrations cannot overuse it.
+pub async fn use_invitation(code: &str, email: &str, _db: Database) -> Result<Invitation> {
+    let filter = doc! {
+        "code_hash": hash_token(code),
+        "expires_at": { "$gt": Utc::now() },
+        "$expr": { "$lt": [ "$uses", "$max_uses" ] },
+        "$or": [ { "email": null }, { "email": normalize_email(email) } ],
+    };
+    let updates = doc! { "$inc": { "uses": 1i64 } };
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let updated = _db.collection("invitations").find_one_and_update(filter, updates, options).await.map_err(|_e| {
+        println!("ERROR [use_invitation] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    match updated {
+        Some(doc) => doc_to_invitation(&doc),
+        None => Err(AppError::InvalidInvitationError),
+    }
+}
+
+
+// Gives back a use when the registration it was counted for did not go through
+pub async fn release_invitation(id: &str, _db: Database) -> Result<()> {
+    let oid = ObjectId::with_string(id).map_err(|_| AppError::InvitationNotFoundError)?;
+    let filter = doc! { "_id": oid, "uses": { "$gt": 0i64 } };
+    _db.collection("invitations").update_one(filter, doc! { "$inc": { "uses": -1i64 } }, None).await.map_err(|_e| {
+        println!("ERROR [release_invitation] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Removes the invitations bound to the email address
+pub async fn delete_email_invitations(email: &str, _db: Database) -> Result<()> {
+    _db.collection("invitations").delete_many(doc! { "email": normalize_email(email) }, None).await.map_err(|_e| {
+        println!("ERROR [delete_email_invitations] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use mongodb::bson::Document;
+
+use crate::Result;
+use crate::auth::models::Role;
+use crate::invitations::models::Invitation;
+
+
+pub fn doc_to_invitation(doc: &Document) -> Result<Invitation> {
+    let result = Invitation {
+        id: doc.get_object_id("_id")?.to_string(),
+        email: doc.get_str("email").ok().map(str::to_owned),
+        role: Role::from_str(doc.get_str("role")?),
+        max_uses: doc.get_i64("max_uses")?,
+        uses: doc.get_i64("uses")?,
+        expires_at: *doc.get_datetime("expires_at")?,
+        created_by: doc.get_str("created_by")?.to_owned(),
+        created_at: *doc.get_datetime("created_at")?,
+    };
+    Ok(result)
+}
+
@@ -1,10 +1,19 @@
 use warp::Filter;
 
-use crate::environment::Environment;
+use crate::environment::{Command, Environment};
+use crate::users::bulk::{self, DuplicateMode, Format};
 
+mod api_keys;
+mod audit;
 mod auth;
+mod avatars;
 mod environment;
 mod error;
+mod invitations;
+mod metrics;
+mod privacy;
+mod roles;
+mod sessions;
 mod users;
 mod articles;
 
@@ -22,16 +31,118 @@ async fn main() {
         Err(_e) => panic!("Unable to read environment configuration: {}", _e),
     };
 
+    // Email addresses are only kept unique by the index, so the server does not start without it
+    let user_indexes = users::service::create_indexes(_env.db()).await;
+    if let Err(_e) = &user_indexes {
+        eprintln!("Unable to create user indexes: {}", _e);
+    }
+    if let Err(_e) = users::service::grandfather_verified_emails(_env.db()).await {
+        eprintln!("Unable to mark existing users as verified: {}", _e);
+    }
+    if let Err(_e) = auth::service::create_indexes(_env.db()).await {
+        eprintln!("Unable to create auth indexes: {}", _e);
+    }
+    if let Err(_e) = auth::revocation::create_indexes(&_env).await {
+        eprintln!("Unable to create revocation indexes: {}", _e);
+    }
+    // Concurrent login attempts are only bounded by the unique key of the login attempts, so the server does not start without it
+    let throttle_indexes = auth::throttle::create_indexes(&_env).await;
+    if let Err(_e) = &throttle_indexes {
+        eprintln!("Unable to create login throttling indexes: {}", _e);
+    }
+    if let Err(_e) = api_keys::service::create_indexes(_env.db()).await {
+        eprintln!("Unable to create API key indexes: {}", _e);
+    }
+    if let Err(_e) = invitations::service::create_indexes(_env.db()).await {
+        eprintln!("Unable to create invitation indexes: {}", _e);
+    }
+    if let Err(_e) = sessions::service::create_indexes(_env.db()).await {
+        eprintln!("Unable to create session indexes: {}", _e);
+    }
+    if let Err(_e) = roles::service::create_indexes(&_env).await {
+        eprintln!("Unable to create role indexes: {}", _e);
+    }
+    if let Err(_e) = audit::service::create_indexes(&_env).await {
+        eprintln!("Unable to create audit log indexes: {}", _e);
+    }
+    if let Err(_e) = privacy::service::create_indexes(&_env).await {
+        eprintln!("Unable to create erasure indexes: {}", _e);
+    }
+
+    if let Some(command) = _env.config().command.clone() {
+        let code = match run_command(&_env, command).await {
+            Ok(code) => code,
+            Err(_e) => {
+                eprintln!("Error: {}", _e);
+                1
+            },
+        };
+        std::process::exit(code);
+    }
+    if user_indexes.is_err() {
+        eprintln!("Accounts sharing an email address can be listed and merged with the merge-duplicates command");
+        std::process::exit(1);
+    }
+    if throttle_indexes.is_err() {
+        std::process::exit(1);
+    }
+
     let auth_routes = auth::routes::routes(_env.clone());
     let user_routes = users::routes::routes(_env.clone());
     let article_routes = articles::routes::routes(_env.clone());
+    let api_key_routes = api_keys::routes::routes(_env.clone());
+    let session_routes = sessions::routes::routes(_env.clone());
+    let invitation_routes = invitations::routes::routes(_env.clone());
+    let role_routes = roles::routes::routes(_env.clone());
+    let audit_routes = audit::routes::routes(_env.clone());
+    let metrics_routes = metrics::routes::routes(_env.clone());
+    let privacy_routes = privacy::routes::routes(_env.clone());
+    let avatar_routes = avatars::routes::routes(_env.clone());
     let error_handler = error::handlers::error_handler;
 
     let routes = article_routes
         .or(auth_routes)
         .or(user_routes)
+        .or(api_key_routes)
+        .or(session_routes)
+        .or(invitation_routes)
+        .or(role_routes)
+        .or(audit_routes)
+        .or(metrics_routes)
+        .or(privacy_routes)
+        .or(avatar_routes)
         .recover(error_handler);
 
     println!("Starting server on {}", _env.config().host);
     warp::serve(routes).run(_env.config().host).await;
 }
+
+// Runs a maintenance task given on the command line and returns the exit code
+async fn run_command(_env: &Environment, command: Command) -> anyhow::Result<i32> {
+    match command {
+        Command::ImportUsers { file, format, mode } => {
+            let format = Format::from_str(&format)?;
+            let mode = DuplicateMode::from_str(&mode)?;
+            let data = std::fs::read(&file)?;
+            let report = bulk::import_users(_env, &data, format, mode, None).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            // Fails when any row did, for use in scripts
+            Ok(if report.failed > 0 { 1 } else { 0 })
+        },
+        Command::ExportUsers { file, format } => {
+            let format = Format::from_str(&format)?;
+            let data = bulk::export_users(_env, format, None).await?;
+            std::fs::write(&file, data)?;
+            println!("Exported users to {}", &file);
+            Ok(0)
+        },
+        Command::MergeDuplicates { merge } => {
+            let groups = bulk::merge_duplicates(_env, merge).await?;
+            println!("{}", serde_json::to_string_pretty(&groups)?);
+            if !merge && !groups.is_empty() {
+                println!("Run again with --merge to merge them into the kept accounts");
+            }
+            Ok(0)
+        },
+    }
+}
@@ -0,0 +1,13 @@
+use warp::Reply;
+use warp::http::header::CONTENT_TYPE;
+
+use crate::WebResult;
+use crate::auth::models::AuthUser;
+use crate::environment::Environment;
+
+// Returns the counters of this instance in the Prometheus text format
+pub async fn get_metrics_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    println!("[get_metrics_handler] Action performed by user {} ({})", _user.id, _user.role);
+    let body = _env.metrics().render();
+    Ok(warp::reply::with_header(body, CONTENT_TYPE, "text/plain; version=0.0.4"))
+}
@@ -0,0 +1,16 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::environment::Environment;
+use crate::metrics::handlers;
+use crate::roles::METRICS_READ;
+
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    let get_metrics_route = warp::get().and(warp::path!("api" / "metrics")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), METRICS_READ))
+        .and_then(handlers::get_metrics_handler));
+
+    get_metrics_route.boxed()
+}
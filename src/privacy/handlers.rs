@@ -0,0 +1,45 @@
+use warp::Reply;
+use warp::reject;
+
+use crate::{audit, roles, users, WebResult};
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
+use crate::privacy::models::TombstoneQuery;
+use crate::privacy::service;
+
+// Downloads the personal data of the current user
+pub async fn get_my_data_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let data = service::get_personal_data(&_env, &_user.id).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::PERSONAL_DATA_EXPORTED, Some(&_user.id), Some(&_user.id), None).await.map_err(reject::custom)?;
+    println!("[get_my_data_handler] User {} downloaded their personal data", _user);
+    Ok(attachment(warp::reply::json(&data), &_user.id))
+}
+
+// Downloads the personal data of a user, to answer their request for it. Users with more permissions than the caller
+// are refused.
+pub async fn get_user_data_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let user = users::service::get_user_by_id(_id.clone(), _env.db()).await.map_err(reject::custom)?;
+    roles::service::ensure_role_within(&_env, &_user, &user.role.unwrap_or(Role::User)).await.map_err(reject::custom)?;
+    let data = service::get_personal_data(&_env, &_id).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::PERSONAL_DATA_EXPORTED, Some(&_user.id), Some(&_id), None).await.map_err(reject::custom)?;
+    println!("[get_user_data_handler][{}] Exported the personal data of user {}", _user, &_id);
+    Ok(attachment(warp::reply::json(&data), &_id))
+}
+
+// Erases a user and their personal data for good, and returns the tombstone of the erasure
+pub async fn erase_user_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let tombstone = service::erase_user(&_env, &_id, &_user).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::USER_ERASED, Some(&_user.id), Some(&_id), None).await.map_err(reject::custom)?;
+    println!("[erase_user_handler][{}] Erased user {}", _user, &_id);
+    Ok(warp::reply::json(&tombstone))
+}
+
+pub async fn get_tombstones_handler(_query: TombstoneQuery, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_tombstones(&_env, &_query).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+fn attachment(reply: impl Reply, user_id: &str) -> impl Reply {
+    let disposition = format!("attachment; filename=\"personal-data-{}.json\"", user_id);
+    warp::reply::with_header(reply, "content-disposition", disposition)
+}
@@ -0,0 +1,5 @@
+pub mod handlers;
+pub mod models;
+pub mod routes;
+pub mod service;
+pub mod utils;
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::models::ApiKey;
+use crate::articles::models::PostedComment;
+use crate::audit::models::AuditEntry;
+use crate::sessions::models::Session;
+use crate::users::models::User;
+
+// Everything stored about a user, as handed over on a request for their personal data
+#[derive(Serialize)]
+pub struct PersonalData {
+    pub exported_at: DateTime<Utc>,
+    pub user: User,
+    pub comments: Vec<PostedComment>,
+    pub sessions: Vec<Session>,
+    pub api_keys: Vec<ApiKey>,
+    pub audit_log: Vec<AuditEntry>,
+}
+
+// Record of an erasure. Only a hash of the email address is kept, to tell whether the data of a restored backup was erased since.
+#[derive(Clone, Serialize, Debug)]
+pub struct Tombstone {
+    pub id: Option<String>,
+    pub user_id: String,
+    pub email_hash: String,
+    pub erased_by: String,
+    pub erased_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct TombstoneQuery {
+    // Finds the erasure of an email address, compared by hash
+    pub email: Option<String>,
+}
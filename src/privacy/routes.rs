@@ -0,0 +1,39 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::environment::Environment;
+use crate::privacy::handlers;
+use crate::roles::USERS_MANAGE;
+
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    // Registered before the route taking a user id, which would otherwise take "me" for one. Like the other routes that
+    // act on the whole account, it is not open to API keys or impersonating admins.
+    let get_my_data_route = warp::get().and(warp::path!("api" / "users" / "me" / "data")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::get_my_data_handler));
+
+    let get_user_data_route = warp::get().and(warp::path!("api" / "users" / String / "data")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::get_user_data_handler));
+
+    let erase_user_route = warp::post().and(warp::path!("api" / "users" / String / "erase")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::erase_user_handler));
+
+    let get_tombstones_route = warp::get().and(warp::path!("api" / "erasures")
+        .and(warp::query())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::get_tombstones_handler));
+
+    let routes = get_my_data_route
+        .or(get_user_data_route)
+        .or(erase_user_route)
+        .or(get_tombstones_route);
+
+    routes.boxed()
+}
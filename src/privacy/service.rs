@@ -0,0 +1,101 @@
+use chrono::Utc;
+use mongodb::bson::doc;
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use tokio::stream::StreamExt;
+
+use crate::{api_keys, articles, audit, auth, invitations, roles, sessions, users, Result};
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
+use crate::error::AppError;
+use crate::privacy::models::{PersonalData, Tombstone, TombstoneQuery};
+use crate::privacy::utils::{doc_to_tombstone, hash_email, tombstone_to_doc};
+
+
+pub async fn create_indexes(_env: &Environment) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "erasures",
+        "indexes": [
+            { "key": { "user_id": 1 }, "name": "user_id" },
+            { "key": { "email_hash": 1 }, "name": "email_hash" },
+            { "key": { "erased_at": -1 }, "name": "erased_at" },
+        ]
+    };
+    _env.db().run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [privacy::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Gathers the account of the user, the comments posted with their email address, their sessions, API keys and audit log
+pub async fn get_personal_data(_env: &Environment, user_id: &str) -> Result<PersonalData> {
+    let user = users::service::get_user_by_id(user_id.to_owned(), _env.db()).await?;
+    let comments = articles::service::get_comments_by_email(&user.email, _env.db()).await?;
+    let sessions = sessions::service::get_sessions(user_id, "", _env.db()).await?;
+    let api_keys = api_keys::service::get_api_keys(user_id, _env.db()).await?;
+    let audit_log = audit::service::get_user_entries(_env, user_id).await?;
+    Ok(PersonalData {
+        exported_at: Utc::now(),
+        user,
+        comments,
+        sessions,
+        api_keys,
+        audit_log,
+    })
+}
+
+
+// Deletes the account of the user and everything tied to it. Their comments and articles are kept without their
+// name, email address or id, and their audit entries without details.
+// The tombstone is recorded first and the user document deleted last, and every step can be run again, so an
+// erasure that failed halfway is completed by erasing the user again. Users with more permissions than the one
+// erasing them are refused.
+pub async fn erase_user(_env: &Environment, user_id: &str, erased_by: &AuthUser) -> Result<Tombstone> {
+    let user = users::service::get_user_by_id(user_id.to_owned(), _env.db()).await?;
+    roles::service::ensure_role_within(_env, erased_by, &user.role.clone().unwrap_or(Role::User)).await?;
+    users::service::ensure_not_last_admin(&user, _env.db()).await?;
+    let tombstone = record_tombstone(_env, user_id, &user.email, &erased_by.id).await?;
+
+    articles::service::clear_author(user_id, _env.db()).await?;
+    invitations::service::delete_email_invitations(&user.email, _env.db()).await?;
+    auth::throttle::reset(_env, &auth::throttle::account_key(&user.email)).await?;
+    audit::service::redact_user_entries(_env, user_id).await?;
+    users::service::delete_account(_env, &user).await?;
+    Ok(tombstone)
+}
+
+
+// Records the tombstone of the erasure, or returns the one of an earlier attempt at erasing the user
+async fn record_tombstone(_env: &Environment, user_id: &str, email: &str, erased_by: &str) -> Result<Tombstone> {
+    let tombstone = Tombstone {
+        id: None,
+        user_id: user_id.to_owned(),
+        email_hash: hash_email(_env, email),
+        erased_by: erased_by.to_owned(),
+        erased_at: Utc::now(),
+    };
+    let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+    let updates = doc! { "$setOnInsert": tombstone_to_doc(&tombstone) };
+    let doc = _env.db().collection("erasures").find_one_and_update(doc! { "user_id": user_id }, updates, options).await.map_err(|_e| {
+        println!("ERROR [erase_user] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    doc_to_tombstone(&doc.ok_or(AppError::DataError)?)
+}
+
+
+// Returns the tombstones of past erasures, newest first
+pub async fn get_tombstones(_env: &Environment, _query: &TombstoneQuery) -> Result<Vec<Tombstone>> {
+    let filter = _query.email.as_deref().map(|email| doc! { "email_hash": hash_email(_env, email) });
+    let options = FindOptions::builder().sort(doc! { "erased_at": -1 }).build();
+    let mut _cursor = _env.db().collection("erasures").find(filter, options).await.map_err(|_e| {
+        println!("ERROR [get_tombstones] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<Tombstone> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        result.push(doc_to_tombstone(&doc?)?);
+    }
+    Ok(result)
+}
@@ -0,0 +1,34 @@
+use mongodb::bson::{doc, Document};
+
+use crate::Result;
+use crate::environment::Environment;
+use crate::privacy::models::Tombstone;
+use crate::users::utils::normalize_email;
+
+
+pub fn tombstone_to_doc(tombstone: &Tombstone) -> Document {
+    doc! {
+        "user_id": &tombstone.user_id,
+        "email_hash": &tombstone.email_hash,
+        "erased_by": &tombstone.erased_by,
+        "erased_at": tombstone.erased_at,
+    }
+}
+
+
+pub fn doc_to_tombstone(doc: &Document) -> Result<Tombstone> {
+    let result = Tombstone {
+        id: Some(doc.get_object_id("_id")?.to_string()),
+        user_id: doc.get_str("user_id")?.to_owned(),
+        email_hash: doc.get_str("email_hash")?.to_owned(),
+        erased_by: doc.get_str("erased_by")?.to_owned(),
+        erased_at: *doc.get_datetime("erased_at")?,
+    };
+    Ok(result)
+}
+
+
+// Keyed digest of an email address, regardless of case
+pub fn hash_email(_env: &Environment, email: &str) -> String {
+    _env.secret_box().digest(&normalize_email(email))
+}
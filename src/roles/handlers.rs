@@ -0,0 +1,46 @@
+use warp::{Reply, reject};
+use serde_json::json;
+
+use crate::WebResult;
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
+use crate::roles::models::{RoleCreateRequest, RoleUpdateRequest};
+use crate::roles::service;
+use crate::roles::utils::{validate_name, validate_permissions};
+
+// Returns every role with its permissions, including the built-in ones
+pub async fn get_roles_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_roles(&_env).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+pub async fn get_role_handler(_name: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_role(&_env, &_name).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+pub async fn create_role_handler(_req: RoleCreateRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    validate_name(&_req.name).map_err(reject::custom)?;
+    let permissions = validate_permissions(&_req.permissions).map_err(reject::custom)?;
+    // Roles are assigned by users:manage holders, so a role may not grant what its creator does not have
+    service::ensure_permissions_within(&_user, &permissions).map_err(reject::custom)?;
+    let result = service::create_role(&_env, &_req.name, permissions).await.map_err(reject::custom)?;
+    println!("[create_role_handler][{}] Created role {} with {:?}", _user, &result.name, &result.permissions);
+    Ok(warp::reply::json(&result))
+}
+
+pub async fn update_role_handler(_name: String, _req: RoleUpdateRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let permissions = validate_permissions(&_req.permissions).map_err(reject::custom)?;
+    service::ensure_role_within(&_env, &_user, &Role::from_str(&_name)).await.map_err(reject::custom)?;
+    service::ensure_permissions_within(&_user, &permissions).map_err(reject::custom)?;
+    let result = service::update_role(&_env, &_name, permissions).await.map_err(reject::custom)?;
+    println!("[update_role_handler][{}] Updated role {} to {:?}", _user, &result.name, &result.permissions);
+    Ok(warp::reply::json(&result))
+}
+
+pub async fn delete_role_handler(_name: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    service::ensure_role_within(&_env, &_user, &Role::from_str(&_name)).await.map_err(reject::custom)?;
+    service::delete_role(&_env, &_name).await.map_err(reject::custom)?;
+    println!("[delete_role_handler][{}] Deleted role {}", _user, &_name);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Role deleted"})))
+}
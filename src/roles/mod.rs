@@ -0,0 +1,26 @@
+pub mod handlers;
+pub mod models;
+pub mod routes;
+pub mod service;
+pub mod utils;
+
+// Permissions checked by the routes. Roles are named sets of these.
+// Create articles, and edit or delete the ones they wrote
+pub const ARTICLES_CREATE: &str = "articles:create";
+// Edit any article and choose the home page articles
+pub const ARTICLES_EDIT: &str = "articles:edit";
+// Everything above, and delete any article
+pub const ARTICLES_WRITE: &str = "articles:write";
+pub const COMMENTS_MODERATE: &str = "comments:moderate";
+pub const USERS_MANAGE: &str = "users:manage";
+pub const USERS_IMPERSONATE: &str = "users:impersonate";
+pub const ROLES_MANAGE: &str = "roles:manage";
+pub const AUDIT_READ: &str = "audit:read";
+pub const METRICS_READ: &str = "metrics:read";
+
+pub const PERMISSIONS: [&str; 9] = [
+    ARTICLES_CREATE, ARTICLES_EDIT, ARTICLES_WRITE, COMMENTS_MODERATE,
+    USERS_MANAGE, USERS_IMPERSONATE, ROLES_MANAGE, AUDIT_READ, METRICS_READ,
+];
+
+pub const BUILT_IN_ROLES: [&str; 4] = ["Admin", "Editor", "Author", "User"];
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+// Named set of permissions. The built-in Admin, Editor, Author and User roles exist without being stored.
+#[derive(Clone, Serialize, Debug)]
+pub struct RoleDefinition {
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub built_in: bool,
+}
+
+#[derive(Deserialize)]
+pub struct RoleCreateRequest {
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleUpdateRequest {
+    pub permissions: Vec<String>,
+}
@@ -0,0 +1,42 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::environment::Environment;
+use crate::roles::{handlers, ROLES_MANAGE};
+
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    let get_roles_route = warp::get().and(warp::path!("api" / "roles")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), ROLES_MANAGE))
+        .and_then(handlers::get_roles_handler));
+
+    let get_role_route = warp::get().and(warp::path!("api" / "roles" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), ROLES_MANAGE))
+        .and_then(handlers::get_role_handler));
+
+    let create_role_route = warp::post().and(warp::path!("api" / "roles")
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), ROLES_MANAGE))
+        .and_then(handlers::create_role_handler));
+
+    let update_role_route = warp::put().and(warp::path!("api" / "roles" / String)
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), ROLES_MANAGE))
+        .and_then(handlers::update_role_handler));
+
+    let delete_role_route = warp::delete().and(warp::path!("api" / "roles" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), ROLES_MANAGE))
+        .and_then(handlers::delete_role_handler));
+
+    let routes = get_roles_route.or(get_role_route)
+        .or(create_role_route)
+        .or(update_role_route)
+        .or(delete_role_route);
+
+    routes.boxed()
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use mongodb::bson::doc;
+use tokio::stream::StreamExt;
+
+use crate::Result;
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
+use crate::error::AppError;
+use crate::roles::{BUILT_IN_ROLES, PERMISSIONS};
+use crate::roles::models::RoleDefinition;
+use crate::roles::utils::{default_role, doc_to_role, is_built_in};
+
+// Role definitions change rarely, so each instance caches them briefly instead of reading them on every request
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    permissions: Vec<String>,
+    checked_at: Instant,
+}
+
+// In-process cache of role permissions keyed by role name, shared by all clones of the environment
+#[derive(Clone, Debug, Default)]
+pub struct RoleCache {
+    entries: Arc<RwLock<HashMap<String, CacheEntry>>>,
+}
+
+impl RoleCache {
+    fn get(&self, name: &str) -> Option<Vec<String>> {
+        let entries = self.entries.read().unwrap();
+        entries.get(name)
+            .filter(|entry| entry.checked_at.elapsed() < CACHE_TTL)
+            .map(|entry| entry.permissions.clone())
+    }
+
+    fn insert(&self, name: &str, permissions: Vec<String>) {
+        let mut entries = self.entries.write().unwrap();
+        entries.insert(name.to_owned(), CacheEntry { permissions, checked_at: Instant::now() });
+    }
+
+    fn remove(&self, name: &str) {
+        let mut entries = self.entries.write().unwrap();
+        entries.remove(name);
+    }
+}
+
+
+pub async fn create_indexes(_env: &Environment) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "roles",
+        "indexes": [
+            { "key": { "name": 1 }, "name": "name", "unique": true },
+        ]
+    };
+    _env.db().run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [roles::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Permissions granted by the role. Admin always has every permission, so it cannot be locked out.
+pub async fn permissions_for(_env: &Environment, role: &Role) -> Result<Vec<String>> {
+    if *role == Role::Admin {
+        return Ok(all_permissions());
+    }
+    let name = role.to_string();
+    if let Some(permissions) = _env.roles().get(&name) {
+        return Ok(permissions);
+    }
+    let permissions = match get_role(_env, &name).await {
+        Ok(definition) => definition.permissions,
+        Err(AppError::RoleNotFoundError) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    _env.roles().insert(&name, permissions.clone());
+    Ok(permissions)
+}
+
+
+fn all_permissions() -> Vec<String> {
+    PERMISSIONS.iter().map(|p| p.to_string()).collect()
+}
+
+
+pub async fn get_roles(_env: &Environment) -> Result<Vec<RoleDefinition>> {
+    let mut _cursor = _env.db().collection("roles").find(None, None).await.map_err(|_e| {
+        println!("ERROR [get_roles] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<RoleDefinition> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        result.push(doc_to_role(&doc?)?);
+    }
+    for name in &BUILT_IN_ROLES {
+        if !result.iter().any(|definition| definition.name == *name) {
+            result.extend(default_role(name));
+        }
+    }
+    result.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(result)
+}
+
+
+// Refuses roles granting a permission the user does not hold, so that users:manage cannot hand out (or reach
+// accounts with) more than the user's own permissions. API key scopes narrow the permissions held.
+pub async fn ensure_role_within(_env: &Environment, _user: &AuthUser, role: &Role) -> Result<()> {
+    let permissions = permissions_for(_env, role).await?;
+    ensure_permissions_within(_user, &permissions).map_err(|e| {
+        println!("[ensure_role_within] User {} may not manage role {}", _user, role);
+        return e;
+    })
+}
+
+
+// Refuses permissions the user does not hold, such as those of a role being defined
+pub fn ensure_permissions_within(_user: &AuthUser, permissions: &[String]) -> Result<()> {
+    if let Some(missing) = permissions.iter().find(|permission| !_user.has_permission(permission)) {
+        println!("[ensure_permissions_within] User {} lacks {}", _user, missing);
+        return Err(AppError::NoPermissionError);
+    }
+    Ok(())
+}
+
+
+pub async fn get_role(_env: &Environment, name: &str) -> Result<RoleDefinition> {
+    let found = _env.db().collection("roles").find_one(doc! { "name": name }, None).await.map_err(|_e| {
+        println!("ERROR [get_role] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    match found {
+        Some(doc) => doc_to_role(&doc),
+        None => default_role(name).ok_or(AppError::RoleNotFoundError),
+    }
+}
+
+
+pub async fn create_role(_env: &Environment, name: &str, permissions: Vec<String>) -> Result<RoleDefinition> {
+    if is_built_in(name) {
+        return Err(AppError::RoleExistsError);
+    }
+    let doc = doc! {
+        "name": name,
+        "permissions": permissions.clone(),
+        "created_at": Utc::now(),
+        "updated_at": Utc::now(),
+    };
+    _env.db().collection("roles").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [create_role] {:?}", _e);
+        return match _e.kind.as_ref() {
+            mongodb::error::ErrorKind::WriteError(mongodb::error::WriteFailure::WriteError(e)) if e.code == 11000 => AppError::RoleExistsError,
+            _ => AppError::DataError,
+        };
+    })?;
+    _env.roles().remove(name);
+    Ok(RoleDefinition { name: name.to_owned(), permissions, built_in: false })
+}
+
+
+// Built-in roles are stored on their first update. Admin keeps every permission regardless.
+pub async fn update_role(_env: &Environment, name: &str, permissions: Vec<String>) -> Result<RoleDefinition> {
+    if Role::from_str(name) == Role::Admin {
+        return Err(AppError::BuiltInRoleError);
+    }
+    let existing = get_role(_env, name).await?;
+    let filter = doc! { "name": name };
+    let updates = doc! {
+        "$set": { "permissions": permissions.clone(), "updated_at": Utc::now() },
+        "$setOnInsert": { "created_at": Utc::now() },
+    };
+    let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+    _env.db().collection("roles").update_one(filter, updates, options).await.map_err(|_e| {
+        println!("ERROR [update_role] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    _env.roles().remove(name);
+    Ok(RoleDefinition { permissions, ..existing })
+}
+
+
+// Only custom roles that are not assigned to any user can be deleted
+pub async fn delete_role(_env: &Environment, name: &str) -> Result<()> {
+    if is_built_in(name) {
+        return Err(AppError::BuiltInRoleError);
+    }
+    let assigned = _env.db().collection("users").count_documents(doc! { "role": name }, None).await.map_err(|_e| {
+        println!("ERROR [delete_role] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if assigned > 0 {
+        return Err(AppError::RoleInUseError);
+    }
+    let _result = _env.db().collection("roles").delete_one(doc! { "name": name }, None).await.map_err(|_e| {
+        println!("ERROR [delete_role] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.deleted_count == 0 {
+        return Err(AppError::RoleNotFoundError);
+    }
+    _env.roles().remove(name);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roles::{ROLES_MANAGE, USERS_MANAGE};
+
+    fn user(permissions: &[&str]) -> AuthUser {
+        AuthUser {
+            id: "5f1b2c3d4e5f6a7b8c9d0e1f".to_owned(),
+            role: Role::Custom("Manager".to_owned()),
+            login_at: Utc::now(),
+            token_id: String::new(),
+            session_id: String::new(),
+            issued_at: 0,
+            expires_at: 0,
+            api_key_id: None,
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            actor: None,
+        }
+    }
+
+    #[test]
+    fn allows_permissions_the_user_holds() {
+        let manager = user(&[ROLES_MANAGE, USERS_MANAGE]);
+        assert!(ensure_permissions_within(&manager, &[]).is_ok());
+        assert!(ensure_permissions_within(&manager, &[USERS_MANAGE.to_owned()]).is_ok());
+    }
+
+    #[test]
+    fn refuses_permissions_the_user_lacks() {
+        let manager = user(&[ROLES_MANAGE]);
+        let result = ensure_permissions_within(&manager, &[ROLES_MANAGE.to_owned(), USERS_MANAGE.to_owned()]);
+        assert!(matches!(result, Err(AppError::NoPermissionError)));
+    }
+
+    #[test]
+    fn refuses_admin_permissions_to_users_managers() {
+        let manager = user(&[USERS_MANAGE]);
+        let result = ensure_permissions_within(&manager, &all_permissions());
+        assert!(matches!(result, Err(AppError::NoPermissionError)));
+        assert!(ensure_permissions_within(&user(&PERMISSIONS), &all_permissions()).is_ok());
+    }
+}
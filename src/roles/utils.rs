@@ -0,0 +1,63 @@
+use mongodb::bson::Document;
+
+use crate::Result;
+use crate::auth::models::Role;
+use crate::error::AppError;
+use crate::roles::{ARTICLES_CREATE, ARTICLES_EDIT, PERMISSIONS};
+use crate::roles::models::RoleDefinition;
+
+
+pub fn doc_to_role(doc: &Document) -> Result<RoleDefinition> {
+    let name = doc.get_str("name")?;
+    let permissions = doc.get_array("permissions")?.iter()
+        .filter_map(|permission| permission.as_str().map(str::to_owned))
+        .collect();
+    let result = RoleDefinition {
+        name: name.to_owned(),
+        permissions,
+        built_in: is_built_in(name),
+    };
+    Ok(result)
+}
+
+
+// Definitions used for the built-in roles until they are stored
+pub fn default_role(name: &str) -> Option<RoleDefinition> {
+    let permissions = match Role::from_str(name) {
+        Role::Admin => PERMISSIONS.iter().map(|p| p.to_string()).collect(),
+        Role::Editor => vec![ARTICLES_CREATE.to_owned(), ARTICLES_EDIT.to_owned()],
+        Role::Author => vec![ARTICLES_CREATE.to_owned()],
+        Role::User => Vec::new(),
+        Role::Custom(_) => return None,
+    };
+    Some(RoleDefinition { name: name.to_owned(), permissions, built_in: true })
+}
+
+
+pub fn is_built_in(name: &str) -> bool {
+    !matches!(Role::from_str(name), Role::Custom(_))
+}
+
+
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty() && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(AppError::InvalidRoleError);
+    }
+    Ok(())
+}
+
+
+pub fn validate_permissions(permissions: &[String]) -> Result<Vec<String>> {
+    let mut result: Vec<String> = Vec::new();
+    for permission in permissions {
+        if !PERMISSIONS.contains(&permission.as_str()) {
+            return Err(AppError::InvalidRoleError);
+        }
+        if !result.contains(permission) {
+            result.push(permission.to_owned());
+        }
+    }
+    Ok(result)
+}
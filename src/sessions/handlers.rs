@@ -0,0 +1,21 @@
+use warp::Reply;
+use serde_json::json;
+use warp::reject;
+
+use crate::WebResult;
+use crate::auth::models::AuthUser;
+use crate::environment::Environment;
+use crate::sessions::service;
+
+// Returns the sessions of the current user, marking the one the request was made from
+pub async fn get_sessions_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_sessions(&_user.id, &_user.session_id, _env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+// Signs out one of the current user's sessions, e.g. on a lost device
+pub async fn delete_session_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    service::end_session(&_env, &_user.id, &_id).await.map_err(reject::custom)?;
+    println!("[delete_session_handler] User {} signed out session {}", _user, &_id);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"Session signed out"})))
+}
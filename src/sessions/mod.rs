@@ -0,0 +1,8 @@
+pub mod handlers;
+pub mod models;
+pub mod routes;
+pub mod service;
+pub mod utils;
+
+// Longest user agent kept for a session
+pub const USER_AGENT_MAX_LENGTH: usize = 512;
@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+// A login and the devices it has been used from. `id` is the session id (`sid`) of its access tokens,
+// which is also the family of its refresh tokens.
+#[derive(Clone, Serialize, Debug)]
+pub struct Session {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // Whether this is the session of the request
+    pub current: bool,
+}
@@ -0,0 +1,22 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::{auth, environment};
+use crate::environment::Environment;
+use crate::sessions::handlers;
+
+pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    let get_sessions_route = warp::get().and(warp::path!("api" / "users" / "me" / "sessions")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::get_sessions_handler));
+
+    let delete_session_route = warp::delete().and(warp::path!("api" / "users" / "me" / "sessions" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::delete_session_handler));
+
+    let routes = get_sessions_route.or(delete_session_route);
+
+    routes.boxed()
+}
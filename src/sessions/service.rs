@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::doc;
+use mongodb::Database;
+use tokio::stream::StreamExt;
+
+use crate::Result;
+use crate::auth::{revocation, service as auth_service};
+use crate::environment::Environment;
+use crate::error::AppError;
+use crate::sessions::models::Session;
+use crate::sessions::utils::doc_to_session;
+
+
+// Sessions are removed by MongoDB itself once their refresh tokens have expired
+pub async fn create_indexes(_db: Database) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "sessions",
+        "indexes": [
+            { "key": { "session_id": 1 }, "name": "session_id", "unique": true },
+            { "key": { "user_id": 1 }, "name": "user_id" },
+            { "key": { "expires_at": 1 }, "name": "expires_at_ttl", "expireAfterSeconds": 0 },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [sessions::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+pub async fn create_session(session_id: &str, user_id: &str, user_agent: Option<String>, ip: Option<String>, expires_at: DateTime<Utc>, _db: Database) -> Result<()> {
+    let now = Utc::now();
+    let mut doc = doc! {
+        "session_id": session_id,
+        "user_id": user_id,
+        "created_at": now,
+        "last_seen_at": now,
+        "expires_at": expires_at,
+    };
+    if let Some(user_agent) = user_agent {
+        doc.insert("user_agent", user_agent);
+    }
+    if let Some(ip) = ip {
+        doc.insert("ip", ip);
+    }
+    _db.collection("sessions").insert_one(doc, None).await.map_err(|_e| {
+        println!("ERROR [create_session] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Records that the session was used to refresh its tokens. A session that is gone is not created again, as a refresh
+// still running while the user signs it out would otherwise bring it back.
+pub async fn touch_session(session_id: &str, user_id: &str, expires_at: DateTime<Utc>, _db: Database) -> Result<()> {
+    let filter = doc! { "session_id": session_id, "user_id": user_id };
+    let updates = doc! { "$set": { "last_seen_at": Utc::now(), "expires_at": expires_at } };
+    _db.collection("sessions").update_one(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [touch_session] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Returns the sessions of the user, most recently used first
+pub async fn get_sessions(user_id: &str, current_session_id: &str, _db: Database) -> Result<Vec<Session>> {
+    let options = mongodb::options::FindOptions::builder().sort(doc! { "last_seen_at": -1 }).build();
+    let mut _cursor = _db.collection("sessions").find(doc! { "user_id": user_id }, options).await.map_err(|_e| {
+        println!("ERROR [get_sessions] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut result: Vec<Session> = Vec::new();
+    while let Some(doc) = _cursor.next().await {
+        let mut session = doc_to_session(&doc?)?;
+        session.current = session.id == current_session_id;
+        result.push(session);
+    }
+    Ok(result)
+}
+
+
+// Signs the session out: its record, its refresh tokens and the access tokens already issued for it
+pub async fn end_session(_env: &Environment, user_id: &str, session_id: &str) -> Result<()> {
+    let filter = doc! { "session_id": session_id, "user_id": user_id };
+    let _result = _env.db().collection("sessions").delete_one(filter, None).await.map_err(|_e| {
+        println!("ERROR [end_session] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.deleted_count == 0 {
+        return Err(AppError::SessionNotFoundError);
+    }
+    auth_service::revoke_refresh_token_family(session_id, _env.db()).await?;
+    revocation::revoke_session_tokens(_env, user_id, session_id).await
+}
+
+
+pub async fn delete_user_sessions(user_id: &str, _db: Database) -> Result<()> {
+    _db.collection("sessions").delete_many(doc! { "user_id": user_id }, None).await.map_err(|_e| {
+        println!("ERROR [delete_user_sessions] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+pub async fn delete_other_sessions(user_id: &str, session_id: &str, _db: Database) -> Result<()> {
+    _db.collection("sessions").delete_many(doc! { "user_id": user_id, "session_id": { "$ne": session_id } }, None).await.map_err(|_e| {
+        println!("ERROR [delete_other_sessions] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use mongodb::bson::Document;
+use warp::http::{HeaderMap, HeaderValue};
+use warp::http::header::USER_AGENT;
+
+use crate::Result;
+use crate::sessions::USER_AGENT_MAX_LENGTH;
+use crate::sessions::models::Session;
+
+
+pub fn doc_to_session(doc: &Document) -> Result<Session> {
+    let result = Session {
+        id: doc.get_str("session_id")?.to_owned(),
+        user_agent: doc.get_str("user_agent").ok().map(str::to_owned),
+        ip: doc.get_str("ip").ok().map(str::to_owned),
+        created_at: *doc.get_datetime("created_at")?,
+        last_seen_at: *doc.get_datetime("last_seen_at")?,
+        expires_at: *doc.get_datetime("expires_at")?,
+        current: false,
+    };
+    Ok(result)
+}
+
+
+pub fn user_agent(headers: &HeaderMap<HeaderValue>) -> Option<String> {
+    headers.get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.chars().take(USER_AGENT_MAX_LENGTH).collect::<String>())
+        .filter(|v| !v.is_empty())
+}
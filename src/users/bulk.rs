@@ -0,0 +1,389 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+
+use crate::{api_keys, articles, audit, auth, avatars, invitations, roles, Result};
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
+use crate::error::{AppError, FieldError};
+use crate::users::IMPORT_MAX_ROWS;
+use crate::users::models::{DuplicateGroup, ExportRow, ImportReport, ImportRow, ImportRowReport, User};
+use crate::users::service;
+use crate::users::utils::normalize_email;
+
+// File formats of imports and exports
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+impl Format {
+    pub fn from_str(format: &str) -> Result<Format> {
+        match format {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::JsonLines),
+            _ => Err(AppError::ValidationError(vec![FieldError::new("format", "invalid", "must be csv or jsonl")])),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::JsonLines => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+        }
+    }
+}
+
+// What happens to imported rows whose email address is already registered
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DuplicateMode {
+    Skip,
+    Update,
+}
+
+impl DuplicateMode {
+    pub fn from_str(mode: &str) -> Result<DuplicateMode> {
+        match mode {
+            "skip" => Ok(DuplicateMode::Skip),
+            "update" => Ok(DuplicateMode::Update),
+            _ => Err(AppError::ValidationError(vec![FieldError::new("mode", "invalid", "must be skip or update")])),
+        }
+    }
+}
+
+// Imports the users of a CSV or JSON Lines file, row by row. A row that fails does not stop the others.
+// Users with a password are created, the others get an invitation bound to their email address.
+// `actor` is the user importing, whose permissions bound the roles given; the command line has none.
+pub async fn import_users(_env: &Environment, data: &[u8], format: Format, mode: DuplicateMode, actor: Option<&AuthUser>) -> Result<ImportReport> {
+    let rows = parse_rows(data, format)?;
+    let mut report = ImportReport::default();
+    let mut seen: HashSet<String> = HashSet::new();
+    for (row, parsed) in rows {
+        let entry = match parsed {
+            Err(message) => failed_row(row, String::new(), vec![FieldError::new("row", "unreadable", &message)]),
+            Ok(import) => {
+                let email = import.email.trim().to_owned();
+                if !email.is_empty() && !seen.insert(email.to_lowercase()) {
+                    failed_row(row, email, vec![FieldError::new("email", "duplicate", "appears more than once in the file")])
+                } else {
+                    match import_row(_env, row, import, mode, actor).await {
+                        Ok(entry) => entry,
+                        Err(AppError::ValidationError(errors)) => failed_row(row, email, errors),
+                        Err(_e) => failed_row(row, email, vec![FieldError::new("row", "failed", &_e.to_string())]),
+                    }
+                }
+            },
+        };
+        match entry.status.as_str() {
+            "created" => report.created += 1,
+            "invited" => report.invited += 1,
+            "updated" => report.updated += 1,
+            "skipped" => report.skipped += 1,
+            _ => report.failed += 1,
+        }
+        report.rows.push(entry);
+    }
+
+    println!("[import_users] Imported {} rows: {} created, {} invited, {} updated, {} skipped, {} failed",
+        report.rows.len(), report.created, report.invited, report.updated, report.skipped, report.failed);
+    let details = format!("created={} invited={} updated={} skipped={} failed={}",
+        report.created, report.invited, report.updated, report.skipped, report.failed);
+    audit::service::record(_env, audit::USERS_IMPORTED, actor.map(|actor| actor.id.as_str()), None, Some(details)).await?;
+    Ok(report)
+}
+
+// Writes every user, without password hashes, as CSV or JSON Lines
+pub async fn export_users(_env: &Environment, format: Format, actor_id: Option<&str>) -> Result<Vec<u8>> {
+    let users = service::get_all_users(_env.db()).await?;
+    let count = users.len();
+    let rows = users.into_iter().map(|user| ExportRow {
+        id: user.id,
+        email: user.email,
+        name: user.name,
+        role: user.role.map(|role| role.to_string()),
+        created_at: user.created_at,
+        verified_at: user.verified_at,
+        disabled_at: user.disabled_at,
+    });
+
+    let data = match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for row in rows {
+                writer.serialize(row).map_err(|_e| {
+                    println!("ERROR [export_users] {:?}", _e);
+                    return AppError::DataError;
+                })?;
+            }
+            writer.into_inner().map_err(|_e| {
+                println!("ERROR [export_users] {:?}", _e);
+                return AppError::DataError;
+            })?
+        },
+        Format::JsonLines => {
+            let mut data = Vec::new();
+            for row in rows {
+                serde_json::to_writer(&mut data, &row).map_err(|_e| {
+                    println!("ERROR [export_users] {:?}", _e);
+                    return AppError::DataError;
+                })?;
+                data.push(b'\n');
+            }
+            data
+        },
+    };
+    audit::service::record(_env, audit::USERS_EXPORTED, actor_id, None, Some(format!("users={}", count))).await?;
+    Ok(data)
+}
+
+// Finds the accounts sharing an email address, whatever its case, which keep the unique email index from being created.
+// With `merge`, each group is merged into the account to keep, the verified one or else the oldest: the others' articles
+// are given to it and they are deleted along with their API keys, two-factor settings, avatar and sessions.
+// Comments are matched by email address and need no change.
+pub async fn merge_duplicates(_env: &Environment, merge: bool) -> Result<Vec<DuplicateGroup>> {
+    let mut by_email: HashMap<String, Vec<User>> = HashMap::new();
+    for user in service::get_all_users(_env.db()).await? {
+        by_email.entry(normalize_email(&user.email)).or_default().push(user);
+    }
+
+    let mut groups = Vec::new();
+    for (email, mut users) in by_email.into_iter().filter(|(_, users)| users.len() > 1) {
+        users.sort_by_key(|user| (user.verified_at.is_none(), user.created_at));
+        let kept_id = users[0].id.clone().unwrap_or_default();
+        let duplicates = users.split_off(1);
+        if merge {
+            for duplicate in &duplicates {
+                let duplicate_id = duplicate.id.clone().unwrap_or_default();
+                service::ensure_not_last_admin(duplicate, _env.db()).await?;
+                articles::service::reassign_author(&duplicate_id, &kept_id, _env.db()).await?;
+                api_keys::service::delete_user_api_keys(&duplicate_id, _env.db()).await?;
+                auth::service::disable_mfa(&duplicate_id, _env.db()).await?;
+                avatars::service::delete_avatar_files(_env, &duplicate_id).await?;
+                service::sign_out_everywhere(_env, &duplicate_id).await?;
+                service::delete_user(&duplicate_id, _env.db()).await?;
+                audit::service::record(_env, audit::USERS_MERGED, None, Some(&kept_id), Some(format!("merged={}", &duplicate_id))).await?;
+            }
+            println!("[merge_duplicates] Merged {} accounts into user {}", duplicates.len(), &kept_id);
+        }
+        groups.push(DuplicateGroup {
+            email,
+            kept_id,
+            duplicate_ids: duplicates.into_iter().filter_map(|user| user.id).collect(),
+        });
+    }
+    Ok(groups)
+}
+
+// Reads the rows of a file along with their row numbers. Rows that cannot be read are kept, to be reported.
+fn parse_rows(data: &[u8], format: Format) -> Result<Vec<(usize, std::result::Result<ImportRow, String>)>> {
+    let mut rows = Vec::new();
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).flexible(true).from_reader(data);
+            for (index, record) in reader.deserialize::<ImportRow>().enumerate() {
+                rows.push((index + 1, record.map_err(|e| e.to_string())));
+            }
+        },
+        Format::JsonLines => {
+            let text = std::str::from_utf8(data)
+                .map_err(|_| AppError::ValidationError(vec![FieldError::new("file", "invalid_encoding", "must be UTF-8")]))?;
+            for (index, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                rows.push((index + 1, serde_json::from_str::<ImportRow>(line).map_err(|e| e.to_string())));
+            }
+        },
+    }
+    if rows.len() > IMPORT_MAX_ROWS {
+        return Err(AppError::ValidationError(vec![FieldError::new("file", "too_many_rows", &format!("must have at most {} rows", IMPORT_MAX_ROWS))]));
+    }
+    Ok(rows)
+}
+
+async fn import_row(_env: &Environment, row: usize, import: ImportRow, mode: DuplicateMode, actor: Option<&AuthUser>) -> Result<ImportRowReport> {
+    let email = import.email.trim().to_owned();
+    let name = import.name.trim().to_owned();
+    let role = import.role.as_deref().map(str::trim).filter(|role| !role.is_empty()).map(Role::from_str);
+    let password = import.password.filter(|password| !password.is_empty());
+
+    let mut errors: Vec<FieldError> = Vec::new();
+    if !email.contains('@') {
+        errors.push(FieldError::new("email", "invalid", "must be an email address"));
+    }
+    if name.is_empty() {
+        errors.push(FieldError::new("name", "required", "must not be empty"));
+    }
+    if let Some(role) = &role {
+        match roles::service::get_role(_env, &role.to_string()).await {
+            Ok(_) => (),
+            Err(AppError::RoleNotFoundError) => errors.push(FieldError::new("role", "unknown", "must be a built-in role or one defined in /api/roles")),
+            Err(_e) => return Err(_e),
+        }
+        if let Some(actor) = actor {
+            match roles::service::ensure_role_within(_env, actor, role).await {
+                Ok(_) | Err(AppError::RoleNotFoundError) => (),
+                Err(AppError::NoPermissionError) => errors.push(FieldError::new("role", "forbidden", "must not grant permissions you do not have")),
+                Err(_e) => return Err(_e),
+            }
+        }
+    }
+    if let Some(password) = &password {
+        if let Err(AppError::ValidationError(password_errors)) = _env.password_policy().validate(password, &email, &name) {
+            errors.extend(password_errors);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::ValidationError(errors));
+    }
+
+    let existing = match service::get_user_by_email(&email, _env.db()).await {
+        Ok(user) => Some(user),
+        Err(AppError::UserNotFound) => None,
+        Err(_e) => return Err(_e),
+    };
+    let mut entry = ImportRowReport {
+        row,
+        email: email.clone(),
+        status: String::new(),
+        id: None,
+        invitation_code: None,
+        invitation_url: None,
+        errors: Vec::new(),
+    };
+    match (existing, mode) {
+        (Some(user), DuplicateMode::Skip) => {
+            entry.id = user.id;
+            entry.status = "skipped".to_owned();
+        },
+        (Some(user), DuplicateMode::Update) => {
+            // Users with more permissions than the importer are left alone
+            if let Some(actor) = actor {
+                roles::service::ensure_role_within(_env, actor, &user.role.clone().unwrap_or(Role::User)).await.map_err(|_e| match _e {
+                    AppError::NoPermissionError => AppError::ValidationError(vec![FieldError::new("email", "forbidden", "belongs to a user with permissions you do not have")]),
+                    _e => _e,
+                })?;
+            }
+            entry.id = Some(update_existing(_env, user, &name, role, password.is_some(), actor).await?);
+            entry.status = "updated".to_owned();
+        },
+        (None, _) => match password {
+            Some(password) => {
+                entry.id = Some(create_user(_env, &email, &name, role.unwrap_or(Role::User), &password).await?);
+                entry.status = "created".to_owned();
+            },
+            None => {
+                let role = role.unwrap_or(Role::User);
+                let expires_at = Utc::now() + chrono::Duration::seconds(_env.config().invitation_ttl);
+                let created_by = actor.map(|actor| actor.id.as_str()).unwrap_or("cli");
+                let (_invitation, code) = invitations::service::create_invitation(Some(&email), &role, 1, expires_at, created_by, _env.db()).await?;
+                entry.invitation_url = Some(format!("{}/register?invitation={}", _env.config().app_url.trim_end_matches('/'), &code));
+                entry.invitation_code = Some(code);
+                entry.status = "invited".to_owned();
+            },
+        },
+    }
+    Ok(entry)
+}
+
+// Same logic as in the registration service, with the role of the row
+async fn create_user(_env: &Environment, email: &str, name: &str, role: Role, password: &str) -> Result<String> {
+    let hash = _env.argon().hash(password).await?;
+    let user = User {
+        id: None,
+        email: email.to_owned(),
+        name: name.to_owned(),
+        password: Some(hash),
+        role: Some(role),
+        created_at: Some(Utc::now()),
+        updated_at: Some(Utc::now()),
+        verified_at: None,
+        disabled_at: None,
+        preferences: None,
+        avatar_url: None,
+    };
+    let id = service::create_user(user, _env.db()).await?;
+    auth::send_verification_email(_env, &id, email, name).await?;
+    Ok(id)
+}
+
+// Updates the name and role of an existing user, with the same checks as a role change through the API. The password
+// is never set from a file: when the row has one, the user is sent a password reset link instead.
+async fn update_existing(_env: &Environment, user: User, name: &str, role: Option<Role>, reset_password: bool, actor: Option<&AuthUser>) -> Result<String> {
+    let id = user.id.clone().unwrap_or_default();
+    if let Some(role) = &role {
+        service::ensure_role_change_allowed(_env, actor, &user, role).await.map_err(|_e| match _e {
+            AppError::NoPermissionError => AppError::ValidationError(vec![FieldError::new("role", "forbidden", "must not be your own or grant permissions you do not have")]),
+            _e => _e,
+        })?;
+    }
+    service::update_profile(&id, Some(name), None, role.as_ref(), None, _env.db()).await?;
+    if reset_password {
+        auth::send_password_reset_email(_env, &id, &user.email, name).await?;
+    }
+    Ok(id)
+}
+
+fn failed_row(row: usize, email: String, errors: Vec<FieldError>) -> ImportRowReport {
+    ImportRowReport {
+        row,
+        email,
+        status: "failed".to_owned(),
+        id: None,
+        invitation_code: None,
+        invitation_url: None,
+        errors,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_formats_and_modes() {
+        assert_eq!(Format::from_str("csv").unwrap(), Format::Csv);
+        assert_eq!(Format::from_str("jsonl").unwrap(), Format::JsonLines);
+        assert!(matches!(Format::from_str("xlsx"), Err(AppError::ValidationError(_))));
+        assert_eq!(DuplicateMode::from_str("skip").unwrap(), DuplicateMode::Skip);
+        assert_eq!(DuplicateMode::from_str("update").unwrap(), DuplicateMode::Update);
+        assert!(matches!(DuplicateMode::from_str("replace"), Err(AppError::ValidationError(_))));
+    }
+
+    #[test]
+    fn numbers_csv_records_after_the_header() {
+        let data = b"email,name,role\n a@example.com , Ann ,Editor\nb@example.com,Bob\n";
+        let rows = parse_rows(data, Format::Csv).unwrap();
+        assert_eq!(rows.len(), 2);
+        let (row, first) = &rows[0];
+        let first = first.as_ref().unwrap();
+        assert_eq!((*row, first.email.as_str(), first.name.as_str(), first.role.as_deref()), (1, "a@example.com", "Ann", Some("Editor")));
+        let (row, second) = &rows[1];
+        assert_eq!((*row, second.as_ref().unwrap().role.as_deref()), (2, None));
+    }
+
+    #[test]
+    fn numbers_json_lines_and_keeps_unreadable_ones() {
+        let data = b"{\"email\":\"a@example.com\",\"name\":\"Ann\"}\n\nnot json\n{\"email\":\"b@example.com\",\"name\":\"Bob\",\"password\":\"x\"}\n";
+        let rows = parse_rows(data, Format::JsonLines).unwrap();
+        assert_eq!(rows.iter().map(|(row, _)| *row).collect::<Vec<_>>(), vec![1, 3, 4]);
+        assert!(rows[1].1.is_err());
+        assert_eq!(rows[2].1.as_ref().unwrap().password.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn refuses_files_with_too_many_rows() {
+        let data = "{\"email\":\"a@example.com\"}\n".repeat(IMPORT_MAX_ROWS + 1);
+        assert!(matches!(parse_rows(data.as_bytes(), Format::JsonLines), Err(AppError::ValidationError(_))));
+        assert!(matches!(parse_rows(b"\xff\n", Format::JsonLines), Err(AppError::ValidationError(_))));
+    }
+}
@@ -3,17 +3,20 @@ use serde_json::json;
 use warp::reject;
 use chrono::Utc;
 
+use crate::{audit, auth, roles};
 use crate::auth::models::{AuthUser, Role};
+use crate::roles::USERS_MANAGE;
 use crate::environment::Environment;
-use crate::users::models::{User, PasswordUpdateRequest};
-use crate::users::service;
+use crate::users::models::{ExportQuery, ImportQuery, User, PasswordUpdateRequest, ProfileUpdateRequest, UserQuery};
+use crate::users::bulk::{self, DuplicateMode, Format};
+use crate::users::{service, utils};
 use crate::{WebResult};
-use crate::error::{UserError, AppError};
+use crate::error::{UserError, AppError, FieldError};
 
-// Returns all users
-pub async fn get_users_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+// Returns a page of users, filtered and sorted as requested
+pub async fn get_users_handler(_query: UserQuery, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
     println!("[get_users_handler] Action performed by user {} ({})", _user.id, _user.role);
-    let result = service::get_users(_env.db()).await.map_err(|e| reject::custom(e))?;
+    let result = service::get_users_page(&_query, _env.db()).await.map_err(reject::custom)?;
     Ok(warp::reply::json(&result))
 }
 
@@ -26,28 +29,29 @@ pub async fn get_user_by_id_handler(_id: String, _env: Environment, _user: AuthU
 
 // Creates new user. Same logic as in registration service.
 pub async fn user_create_handler(mut _req: User, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
-    match service::get_user_by_email(&_req.email, _env.db()).await {
-        Err(_e) => (),
-        Ok(existing) => {
-            println!("[user_create_handler] User {} already exists", &existing.email);
-            return Ok(warp::reply::json(&json!({"status":"error", "message":"Unable to create user, email already registered"})))
-        },
-    }
-
-    let hash = _env.argon().hasher().with_password(&_req.password.unwrap()).hash().unwrap();
+    let password = _req.password.clone().unwrap_or_default();
+    _env.password_policy().validate(&password, &_req.email, &_req.name).map_err(reject::custom)?;
+    let hash = _env.argon().hasher().with_password(&password).hash().unwrap();
     _req.password = Some(hash);
     _req.role = Some(Role::User);
     _req.created_at = Some(Utc::now());
     _req.updated_at = Some(Utc::now());
+    _req.verified_at = None;
 
     let email = _req.email.clone();
+    let name = _req.name.clone();
     match service::create_user(_req, _env.db()).await {
+        Err(AppError::EmailExistsError) => {
+            println!("[user_create_handler] User {} already exists", &email);
+            return Err(warp::reject::custom(AppError::EmailExistsError))
+        },
         Err(e) => {
             println!("[user_create_handler] Error creating user {}: {:?}", &email, e);
             return Err(warp::reject::custom(UserError::CreateError))
         },
-        _ => {
+        Ok(id) => {
             println!("[user_create_handler] User creation successful: {:?}", &email);
+            auth::send_verification_email(&_env, &id, &email, &name).await.map_err(reject::custom)?;
             return Ok(warp::reply::json(&json!({"status": "success"})));
         }
     }
@@ -56,14 +60,27 @@ pub async fn user_create_handler(mut _req: User, _env: Environment, _user: AuthU
 // Updates user
 pub async fn user_update_handler(_req: User, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
     println!("[user_update_handler][{}] Updating user {}", _user, &_req.email);
-    let _result = service::update_user(_req, _env.db()).await.map(|_e| UserError::UpdateError);
+    let existing = service::get_user_by_id(_req.id.clone().unwrap_or_default(), _env.db()).await.map_err(reject::custom)?;
+    // Users with more permissions than the caller cannot be changed by them
+    let current_role = existing.role.clone().unwrap_or(Role::User);
+    roles::service::ensure_role_within(&_env, &_user, &current_role).await.map_err(reject::custom)?;
+    // Only roles that are built in or defined in the roles collection can be assigned, and only within the caller's permissions
+    if let Some(role) = &_req.role {
+        service::ensure_role_change_allowed(&_env, Some(&_user), &existing, role).await.map_err(reject::custom)?;
+    }
+    service::update_user(_req, _env.db()).await.map_err(reject::custom)?;
     Ok(warp::reply::json(&json!({"status":"success", "message":"User updated"})))
 }
 
 // Changes own or other's password if admin
 pub async fn password_update_handler(mut _req: PasswordUpdateRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
-    // Reject non-admins changing passwords of other users
-    if _user.role != Role::Admin && _user.id != _req.id.to_string() {
+    // Admins impersonating a user see what the user sees, but do not get to change credentials
+    if _user.actor.is_some() {
+        println!("[password_update_handler] Refused password change by {:?} impersonating {}", &_user.actor, _user.id);
+        return Err(warp::reject::custom(AppError::NoPermissionError));
+    }
+    // Reject users without users:manage changing passwords of other users
+    if !_user.has_permission(USERS_MANAGE) && _user.id != _req.id.to_string() {
         return Err(warp::reject::custom(UserError::UpdateError));
     }
 
@@ -71,12 +88,16 @@ pub async fn password_update_handler(mut _req: PasswordUpdateRequest, _env: Envi
 
     let target_user_id = user.id.clone().unwrap().to_string();
     println!("[password_update_handler] Action performed by {} on {}", _user.id, target_user_id);
+    // Passwords of users with more permissions than the caller cannot be changed by them
+    if _user.id != target_user_id {
+        roles::service::ensure_role_within(&_env, &_user, &user.role.clone().unwrap_or(Role::User)).await.map_err(reject::custom)?;
+    }
     // current_password is required for users/admins to change their own passwords, but allow admins change others'
-    if (_user.id != user.id.clone().unwrap().to_string() && _user.role != Role::Admin) || _user.id == target_user_id {
+    if (_user.id != user.id.clone().unwrap().to_string() && !_user.has_permission(USERS_MANAGE)) || _user.id == target_user_id {
         let is_valid = _env
             .argon()
             .verifier()
-            .with_hash(&user.password.unwrap())
+            .with_hash(&user.password.clone().ok_or(warp::reject::custom(AppError::WrongCredentialsError))?)
             .with_password(&_req.current_password)
             .verify()
             .or(Err(warp::reject::custom(UserError::UpdateError)))?;
@@ -86,8 +107,145 @@ pub async fn password_update_handler(mut _req: PasswordUpdateRequest, _env: Envi
         }
     }
 
-    let hash = _env.argon().hasher().with_password(&_req.new_password).hash().unwrap();
+    _env.password_policy().validate(&_req.new_password, &user.email, &user.name).map_err(reject::custom)?;
+    let hash = _env.argon().hash(&_req.new_password).await.map_err(reject::custom)?;
     user.password = Some(hash);
-    let _result = service::update_user_password(user, _env.db()).await.map(|_e| UserError::UpdateError);
+    service::update_user_password(user, _env.db()).await.map_err(reject::custom)?;
+    // Whoever knew the old password is signed out, except for the session the user changed their own password from
+    if _user.id == target_user_id && !_user.session_id.is_empty() {
+        service::sign_out_other_sessions(&_env, &target_user_id, &_user.session_id).await.map_err(reject::custom)?;
+    } else {
+        service::sign_out_everywhere(&_env, &target_user_id).await.map_err(reject::custom)?;
+    }
     Ok(warp::reply::json(&json!({"status":"success", "message":"Password updated"})))
 }
+
+// Clears the failed login attempts of a user that got locked out, unless they have more permissions than the caller
+pub async fn unlock_user_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let user = service::get_user_by_id(_id.clone(), _env.db()).await.map_err(|e| reject::custom(e))?;
+    roles::service::ensure_role_within(&_env, &_user, &user.role.clone().unwrap_or(Role::User)).await.map_err(reject::custom)?;
+    auth::throttle::reset(&_env, &auth::throttle::account_key(&user.email)).await.map_err(|e| reject::custom(e))?;
+    println!("[unlock_user_handler][{}] Unlocked user {}", _user, &user.email);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"User unlocked"})))
+}
+
+// Returns the current user
+pub async fn get_me_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let result = service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+// Updates the profile of the current user. Only admins signed in themselves may change their own role,
+// and changing the email address takes a new verification of it.
+pub async fn update_me_handler(_req: ProfileUpdateRequest, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let user = service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+
+    if let Some(role) = &_req.role {
+        if _user.role != Role::Admin || _user.api_key_id.is_some() || _user.actor.is_some() {
+            println!("[update_me_handler] User {} may not change their own role", _user);
+            return Err(reject::custom(AppError::NoPermissionError));
+        }
+        roles::service::get_role(&_env, &role.to_string()).await.map_err(reject::custom)?;
+        roles::service::ensure_role_within(&_env, &_user, role).await.map_err(reject::custom)?;
+    }
+
+    let name = _req.name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+    let email = _req.email.as_deref().map(utils::normalize_email).filter(|email| *email != utils::normalize_email(&user.email));
+    let email = email.as_deref();
+    if let Some(email) = email {
+        // The email address is where password resets go, so API keys and impersonating admins cannot change it
+        if _user.api_key_id.is_some() || _user.actor.is_some() {
+            return Err(reject::custom(AppError::NoPermissionError));
+        }
+        if !email.contains('@') {
+            return Err(reject::custom(AppError::ValidationError(vec![FieldError::new("email", "invalid", "must be an email address")])));
+        }
+    }
+
+    let preferences = match _req.preferences {
+        Some(changes) => {
+            let mut preferences = user.preferences.clone().unwrap_or_default();
+            for (key, value) in changes {
+                if value.is_null() {
+                    preferences.remove(&key);
+                } else {
+                    preferences.insert(key, value);
+                }
+            }
+            utils::validate_preferences(&preferences).map_err(reject::custom)?;
+            Some(utils::preferences_to_doc(&preferences).map_err(reject::custom)?)
+        },
+        None => None,
+    };
+
+    if matches!(&_req.role, Some(role) if *role != Role::Admin) {
+        service::ensure_not_last_admin(&user, _env.db()).await.map_err(reject::custom)?;
+    }
+    service::update_profile(&_user.id, name, email, _req.role.as_ref(), preferences, _env.db()).await.map_err(reject::custom)?;
+    if let Some(email) = email {
+        println!("[update_me_handler] User {} changed their email address", _user);
+        auth::send_verification_email(&_env, &_user.id, email, name.unwrap_or(&user.name)).await.map_err(reject::custom)?;
+    }
+    let result = service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&result))
+}
+
+// Deletes the account of the current user and signs out all of its sessions
+pub async fn delete_me_handler(_env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let user = service::get_user_by_id(_user.id.clone(), _env.db()).await.map_err(reject::custom)?;
+    service::delete_account(&_env, &user).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::USER_DELETED, Some(&_user.id), Some(&_user.id), None).await.map_err(reject::custom)?;
+    println!("[delete_me_handler] User {} deleted their account", _user);
+    Ok(auth::cookies::clear_session(&_env, warp::reply::json(&json!({"status":"success", "message":"Account deleted"}))))
+}
+
+// Deletes a user for good. Their comments are kept, without their name and email address.
+pub async fn delete_user_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let user = service::get_user_by_id(_id.clone(), _env.db()).await.map_err(reject::custom)?;
+    // Users with more permissions than the caller cannot be deleted, disabled or enabled by them
+    roles::service::ensure_role_within(&_env, &_user, &user.role.clone().unwrap_or(Role::User)).await.map_err(reject::custom)?;
+    service::delete_account(&_env, &user).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::USER_DELETED, Some(&_user.id), Some(&_id), None).await.map_err(reject::custom)?;
+    println!("[delete_user_handler][{}] Deleted user {}", _user, &_id);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"User deleted"})))
+}
+
+// Deactivates a user, who can no longer sign in, and signs out all of their sessions
+pub async fn disable_user_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let user = service::get_user_by_id(_id.clone(), _env.db()).await.map_err(reject::custom)?;
+    roles::service::ensure_role_within(&_env, &_user, &user.role.clone().unwrap_or(Role::User)).await.map_err(reject::custom)?;
+    service::ensure_not_last_admin(&user, _env.db()).await.map_err(reject::custom)?;
+    service::set_user_disabled(&_id, true, _env.db()).await.map_err(reject::custom)?;
+    service::sign_out_everywhere(&_env, &_id).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::USER_DISABLED, Some(&_user.id), Some(&_id), None).await.map_err(reject::custom)?;
+    println!("[disable_user_handler][{}] Disabled user {}", _user, &user.email);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"User disabled"})))
+}
+
+pub async fn enable_user_handler(_id: String, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let user = service::get_user_by_id(_id.clone(), _env.db()).await.map_err(reject::custom)?;
+    roles::service::ensure_role_within(&_env, &_user, &user.role.unwrap_or(Role::User)).await.map_err(reject::custom)?;
+    service::set_user_disabled(&_id, false, _env.db()).await.map_err(reject::custom)?;
+    audit::service::record(&_env, audit::USER_ENABLED, Some(&_user.id), Some(&_id), None).await.map_err(reject::custom)?;
+    println!("[enable_user_handler][{}] Enabled user {}", _user, &_id);
+    Ok(warp::reply::json(&json!({"status":"success", "message":"User enabled"})))
+}
+
+// Imports users from a CSV or JSON Lines body and reports the outcome of each row
+pub async fn import_users_handler(_query: ImportQuery, _body: warp::hyper::body::Bytes, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let format = Format::from_str(_query.format.as_deref().unwrap_or("csv")).map_err(reject::custom)?;
+    let mode = DuplicateMode::from_str(_query.mode.as_deref().unwrap_or("skip")).map_err(reject::custom)?;
+    println!("[import_users_handler][{}] Importing {} bytes of {:?} in {:?} mode", _user, _body.len(), format, mode);
+    let report = bulk::import_users(&_env, &_body, format, mode, Some(&_user)).await.map_err(reject::custom)?;
+    Ok(warp::reply::json(&report))
+}
+
+// Downloads every user, without password hashes, as CSV or JSON Lines
+pub async fn export_users_handler(_query: ExportQuery, _env: Environment, _user: AuthUser) -> WebResult<impl Reply> {
+    let format = Format::from_str(_query.format.as_deref().unwrap_or("csv")).map_err(reject::custom)?;
+    println!("[export_users_handler][{}] Exporting users as {:?}", _user, format);
+    let data = bulk::export_users(&_env, format, Some(&_user.id)).await.map_err(reject::custom)?;
+    let disposition = format!("attachment; filename=\"users.{}\"", format.extension());
+    let reply = warp::reply::with_header(data, "content-type", format.content_type());
+    Ok(warp::reply::with_header(reply, "content-disposition", disposition))
+}
@@ -1,5 +1,28 @@
+pub mod bulk;
 pub mod handlers;
 pub mod models;
 pub mod routes;
 pub mod service;
 pub mod utils;
+
+// Limits of the preferences users keep on their profile
+pub const PREFERENCES_MAX_KEYS: usize = 50;
+pub const PREFERENCE_KEY_MAX_LENGTH: usize = 64;
+pub const PREFERENCE_VALUE_MAX_LENGTH: usize = 1024;
+
+// Page size of the user listing
+pub const USERS_PAGE_DEFAULT_LIMIT: i64 = 20;
+pub const USERS_PAGE_MAX_LIMIT: i64 = 100;
+
+// Limits of a bulk import, as one request
+pub const IMPORT_MAX_BYTES: u64 = 5 * 1024 * 1024;
+pub const IMPORT_MAX_ROWS: usize = 10_000;
+
+// Code of the errors MongoDB returns for writes refused by a unique index
+pub const DUPLICATE_KEY_CODE: i32 = 11000;
+
+// Migration marking the accounts that predate email verification as verified, run once
+pub const VERIFIED_EMAIL_MIGRATION: &str = "grandfather_verified_emails";
+
+// Seconds an admin being removed is left out of the count of active admins, see service::ensure_not_last_admin
+pub const LEAVING_ADMIN_TIMEOUT: i64 = 60;
@@ -1,6 +1,8 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use crate::auth::models::Role;
+use crate::error::FieldError;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct User {
@@ -12,6 +14,16 @@ pub struct User {
     pub role: Option<Role>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub verified_at: Option<DateTime<Utc>>,
+    // Disabled users cannot sign in and their API keys stop working
+    #[serde(skip_deserializing)]
+    pub disabled_at: Option<DateTime<Utc>>,
+    // Only changed by the user, through PATCH /api/users/me
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub preferences: Option<Map<String, Value>>,
+    // Set once the user uploaded an avatar, through PUT /api/users/me/avatar
+    #[serde(skip_deserializing)]
+    pub avatar_url: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -20,3 +32,115 @@ pub struct PasswordUpdateRequest {
     pub current_password: String,
     pub new_password: String,
 }
+
+// Changes to the current user's profile. Preferences are merged into the stored ones, null removes a preference.
+#[derive(Deserialize)]
+pub struct ProfileUpdateRequest {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub role: Option<Role>,
+    pub preferences: Option<Map<String, Value>>,
+}
+
+// Filters and sort of the user listing. Pages are fetched with the `next_cursor` of the previous page.
+#[derive(Deserialize)]
+pub struct UserQuery {
+    pub role: Option<String>,
+    // Prefixes, matched regardless of case
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub disabled: Option<bool>,
+    // created_at, email or name, descending when prefixed with '-'. Newest first by default.
+    pub sort: Option<String>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct UserPage {
+    pub items: Vec<User>,
+    // Users matching the filters, on all pages
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+// Position after the last user of a page: its value of the sort field and its id
+#[derive(Serialize, Deserialize)]
+pub struct UserCursor {
+    pub sort: String,
+    pub value: String,
+    pub id: String,
+}
+
+// Options of a bulk import: csv or jsonl, and whether users that already exist are skipped or updated
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    pub format: Option<String>,
+    pub mode: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+}
+
+// Row of an imported file. Users without a password get an invitation to register instead.
+#[derive(Deserialize, Debug)]
+pub struct ImportRow {
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub name: String,
+    pub role: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub invited: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub rows: Vec<ImportRowReport>,
+}
+
+// Outcome of one row: created, invited, updated, skipped or failed
+#[derive(Serialize)]
+pub struct ImportRowReport {
+    // Line number for JSON Lines, record number after the header for CSV
+    pub row: usize,
+    pub email: String,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // Only for invited users, the code is not stored and cannot be shown again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invitation_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invitation_url: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+// Accounts sharing an email address, whatever its case, and the one they are merged into
+#[derive(Serialize)]
+pub struct DuplicateGroup {
+    pub email: String,
+    pub kept_id: String,
+    pub duplicate_ids: Vec<String>,
+}
+
+// Exported user, never with its password hash
+#[derive(Serialize)]
+pub struct ExportRow {
+    pub id: Option<String>,
+    pub email: String,
+    pub name: String,
+    pub role: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
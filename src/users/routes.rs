@@ -2,43 +2,106 @@ use warp::{Filter, Reply};
 use warp::filters::BoxedFilter;
 
 use crate::{auth, environment};
-use crate::auth::models::Role;
+use crate::roles::USERS_MANAGE;
 use crate::environment::Environment;
-use crate::users::handlers;
+use crate::users::{handlers, IMPORT_MAX_BYTES};
 
 pub fn routes(_env: Environment) -> BoxedFilter<(impl Reply, )> {
+    // Registered before the routes taking a user id, which would otherwise take "me" for one
+    let get_me_route = warp::get().and(warp::path!("api" / "users" / "me")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::authenticated(_env.clone()))
+        .and_then(handlers::get_me_handler));
+
+    let update_me_route = warp::patch().and(warp::path!("api" / "users" / "me")
+        .and(warp::body::json())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::authenticated(_env.clone()))
+        .and_then(handlers::update_me_handler));
+
+    let delete_me_route = warp::delete().and(warp::path!("api" / "users" / "me")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_session(_env.clone()))
+        .and_then(handlers::delete_me_handler));
+
+    // Also registered before the routes taking a user id
+    let export_users_route = warp::get().and(warp::path!("api" / "users" / "export")
+        .and(warp::query())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::export_users_handler));
+
+    let import_users_route = warp::post().and(warp::path!("api" / "users" / "import")
+        .and(warp::query())
+        .and(warp::body::content_length_limit(IMPORT_MAX_BYTES))
+        .and(warp::body::bytes())
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::import_users_handler));
+
     let get_users_route = warp::get().and(warp::path!("api" / "users")
+        .and(warp::query())
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
         .and_then(handlers::get_users_handler));
 
     let get_user_route = warp::get().and(warp::path!("api" / "users" / String)
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
         .and_then(handlers::get_user_by_id_handler));
 
     let user_create_route = warp::post().and(warp::path!("api" / "users")
         .and(warp::body::json())
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
         .and_then(handlers::user_create_handler));
 
     let user_update_route = warp::put().and(warp::path!("api" / "users")
         .and(warp::body::json())
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::with_auth(Role::Admin))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
         .and_then(handlers::user_update_handler));
 
     let user_password_update_route = warp::put().and(warp::path!("api" / "users" / "changePassword")
         .and(warp::body::json())
         .and(environment::with_env(_env.clone()))
-        .and(auth::middleware::authenticated())
+        .and(auth::middleware::with_session(_env.clone()))
         .and_then(handlers::password_update_handler));
 
-    let routes = get_users_route.or(get_user_route)
+    let unlock_user_route = warp::post().and(warp::path!("api" / "users" / String / "unlock")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::unlock_user_handler));
+
+    let delete_user_route = warp::delete().and(warp::path!("api" / "users" / String)
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::delete_user_handler));
+
+    let disable_user_route = warp::post().and(warp::path!("api" / "users" / String / "disable")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::disable_user_handler));
+
+    let enable_user_route = warp::post().and(warp::path!("api" / "users" / String / "enable")
+        .and(environment::with_env(_env.clone()))
+        .and(auth::middleware::with_permission(_env.clone(), USERS_MANAGE))
+        .and_then(handlers::enable_user_handler));
+
+    let routes = get_me_route
+        .or(update_me_route)
+        .or(delete_me_route)
+        .or(export_users_route)
+        .or(import_users_route)
+        .or(get_users_route)
+        .or(get_user_route)
         .or(user_create_route)
         .or(user_update_route)
-        .or(user_password_update_route);
+        .or(user_password_update_route)
+        .or(unlock_user_route)
+        .or(delete_user_route)
+        .or(disable_user_route)
+        .or(enable_user_route);
 
     routes.boxed()
 }
@@ -1,16 +1,75 @@
-use chrono::Utc;
-use mongodb::bson::{doc};
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, Document};
 use mongodb::{Database};
+use mongodb::options::FindOptions;
 
-use crate::Result;
+use crate::{api_keys, articles, auth, avatars, roles, sessions, Result};
+use crate::auth::models::{AuthUser, Role};
+use crate::environment::Environment;
 use crate::error::{AppError};
-use crate::users::models::{User};
-use crate::users::utils::{parse_users, parse_user, user_to_doc};
+use crate::users::{LEAVING_ADMIN_TIMEOUT, USERS_PAGE_DEFAULT_LIMIT, USERS_PAGE_MAX_LIMIT, VERIFIED_EMAIL_MIGRATION};
+use crate::users::models::{User, UserPage, UserQuery};
+use crate::users::utils::{cursor_filter, email_collation, encode_cursor, is_duplicate_key, normalize_email, parse_sort, parse_users, parse_user, user_filter, user_to_doc};
+
+
+// Indexes backing the sorts and filters of the user listing, and keeping email addresses unique regardless of case
+pub async fn create_indexes(_db: Database) -> Result<()> {
+    let command = doc! {
+        "createIndexes": "users",
+        "indexes": [
+            { "key": { "created_at": 1, "_id": 1 }, "name": "created_at_id" },
+            { "key": { "name": 1, "_id": 1 }, "name": "name_id" },
+            { "key": { "role": 1 }, "name": "role" },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [users::create_indexes] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    // Created on its own, as it cannot be while several accounts share an email address
+    let command = doc! {
+        "createIndexes": "users",
+        "indexes": [
+            { "key": { "email": 1 }, "name": "email_unique", "unique": true, "collation": { "locale": "en", "strength": 2 } },
+        ]
+    };
+    _db.run_command(command, None).await.map_err(|_e| {
+        println!("ERROR [users::create_indexes] Unable to create the unique email index, accounts sharing an email address have to be merged or removed first: {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Accounts created before email verification never got a link, so they would be locked out by REQUIRE_VERIFIED_EMAIL.
+// The first start with this migration counts every existing account as verified, and records that it ran.
+pub async fn grandfather_verified_emails(_db: Database) -> Result<()> {
+    let migrations = _db.collection("migrations");
+    let done = migrations.find_one(doc! { "_id": VERIFIED_EMAIL_MIGRATION }, None).await.map_err(|_e| {
+        println!("ERROR [grandfather_verified_emails] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if done.is_some() {
+        return Ok(());
+    }
+    let now = Utc::now();
+    let filter = doc! { "verified_at": { "$exists": false } };
+    let _result = _db.collection("users").update_many(filter, doc! { "$set": { "verified_at": now } }, None).await.map_err(|_e| {
+        println!("ERROR [grandfather_verified_emails] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    migrations.insert_one(doc! { "_id": VERIFIED_EMAIL_MIGRATION, "ran_at": now }, None).await.map_err(|_e| {
+        println!("ERROR [grandfather_verified_emails] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    println!("[grandfather_verified_emails] Marked {} existing users as verified", _result.modified_count);
+    Ok(())
+}
 
 
 pub async fn get_user_by_id(_id: String, _db: Database) -> Result<User> {
     println!("[get_user_by_id] id {:?}", &_id);
-    let oid = mongodb::bson::oid::ObjectId::with_string(&_id).unwrap();
+    let oid = mongodb::bson::oid::ObjectId::with_string(&_id).map_err(|_e| AppError::UserNotFound)?;
     let filter = doc! { "_id": oid };
     let mut _cursor = _db.collection("users").find(filter, None).await.map_err(|_e| { 
         println!("ERROR [get_user_by_id] {:?}", _e);
@@ -22,8 +81,9 @@ pub async fn get_user_by_id(_id: String, _db: Database) -> Result<User> {
 
 pub async fn get_user_by_email(email: &str, _db: Database) -> Result<User> {
     println!("[get_user_by_email] email {:?}", &email);
-    let filter = doc! { "email": email };
-    let mut _cursor = _db.collection("users").find(filter, None).await.map_err(|_e| { 
+    let filter = doc! { "email": normalize_email(email) };
+    let options = FindOptions::builder().collation(email_collation()).build();
+    let mut _cursor = _db.collection("users").find(filter, options).await.map_err(|_e| { 
         println!("ERROR [get_user_by_email] {:?}", _e);
         return AppError::DataError;
     })?;
@@ -31,22 +91,76 @@ pub async fn get_user_by_email(email: &str, _db: Database) -> Result<User> {
 }
 
 
-pub async fn get_users(_db: Database) -> Result<Vec<User>> {
-    let mut _cursor = _db.collection("users").find(None, None).await.map_err(|_e| { 
-        println!("ERROR [get_user_by_email] {:?}", _e);
+// Users are linked to their identity provider account by `oidc_subject`, the issuer and subject of their ID tokens
+pub async fn get_user_by_oidc_subject(subject: &str, _db: Database) -> Result<User> {
+    let filter = doc! { "oidc_subject": subject };
+    let mut _cursor = _db.collection("users").find(filter, None).await.map_err(|_e| {
+        println!("ERROR [get_user_by_oidc_subject] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    return parse_user(_cursor).await;
+}
+
+
+// Returns one page of the users matching the query, along with their total count
+pub async fn get_users_page(_query: &UserQuery, _db: Database) -> Result<UserPage> {
+    let sort = _query.sort.clone().unwrap_or_else(|| "-created_at".to_owned());
+    let (field, descending) = parse_sort(&sort)?;
+    let limit = _query.limit.unwrap_or(USERS_PAGE_DEFAULT_LIMIT).clamp(1, USERS_PAGE_MAX_LIMIT);
+
+    let filter = user_filter(_query);
+    let total = _db.collection("users").count_documents(filter.clone(), None).await.map_err(|_e| {
+        println!("ERROR [get_users_page] {:?}", _e);
         return AppError::DataError;
     })?;
-    return parse_users(_cursor).await;
+    let page_filter = match &_query.cursor {
+        Some(cursor) => doc! { "$and": [filter, cursor_filter(cursor, &sort, field, descending)?] },
+        None => filter,
+    };
+
+    let direction = if descending { -1 } else { 1 };
+    let mut sort_doc = Document::new();
+    sort_doc.insert(field, direction);
+    sort_doc.insert("_id", direction);
+    // One more than the page size, to know whether there is a next page
+    let options = FindOptions::builder().sort(sort_doc).limit(limit + 1).build();
+    let _cursor = _db.collection("users").find(page_filter, options).await.map_err(|_e| {
+        println!("ERROR [get_users_page] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    let mut items = parse_users(_cursor).await?;
+    let mut next_cursor = None;
+    if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        next_cursor = items.last().map(|user| encode_cursor(&sort, field, user));
+    }
+    Ok(UserPage { items, total, next_cursor })
+}
+
+
+// Returns every user, oldest first, for exports
+pub async fn get_all_users(_db: Database) -> Result<Vec<User>> {
+    let options = FindOptions::builder().sort(doc! { "created_at": 1, "_id": 1 }).build();
+    let _cursor = _db.collection("users").find(None, options).await.map_err(|_e| {
+        println!("ERROR [get_all_users] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    parse_users(_cursor).await
 }
 
 
-pub async fn create_user(_req: User, _db: Database) -> Result<()> {
+// Inserts the user and returns its new id
+pub async fn create_user(_req: User, _db: Database) -> Result<String> {
     let doc = user_to_doc(&_req);
-    let _cursor = _db.collection("users").insert_one(doc, None).await.map_err(|_e| { 
+    let _result = _db.collection("users").insert_one(doc, None).await.map_err(|_e| {
+        if is_duplicate_key(&_e) {
+            return AppError::EmailExistsError;
+        }
         println!("ERROR [create_user] {:?}", _e);
         return AppError::DataError;
     })?;
-    Ok(())
+    let oid = _result.inserted_id.as_object_id().ok_or(AppError::DataError)?;
+    Ok(oid.to_string())
 }
 
 
@@ -58,13 +172,16 @@ pub async fn update_user(_req: User, _db: Database) -> Result<()> {
 
     let filter = doc! { "_id": oid };
     let updates = doc! { "$set": {
-        "email": &_req.email,
+        "email": normalize_email(&_req.email),
         "name": &_req.name,
         "role": &role.to_string(),
-        "email": &_req.email,
-        "updated_at": Utc::now()}
+        "updated_at": Utc::now()},
+        "$unset": { "leaving_admin_at": "" }
         };
-    let _cursor = _db.collection("users").update_one(filter, updates, None).await.map_err(|_e| { 
+    let _cursor = _db.collection("users").update_one(filter, updates, None).await.map_err(|_e| {
+        if is_duplicate_key(&_e) {
+            return AppError::EmailExistsError;
+        }
         println!("ERROR [update_user] {:?}", _e);
         return AppError::DataError;
     })?;
@@ -88,3 +205,226 @@ pub async fn update_user_password(_req: User, _db: Database) -> Result<()> {
     })?;
     Ok(())
 }
+
+
+// Swaps the password hash for one made with other parameters, unless the password was changed in the meantime.
+// Returns whether the hash was replaced.
+pub async fn replace_password_hash(_id: &str, old_hash: &str, new_hash: &str, _db: Database) -> Result<bool> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::UserNotFound)?;
+    let filter = doc! { "_id": oid, "password": old_hash };
+    let updates = doc! { "$set": { "password": new_hash, "updated_at": Utc::now() } };
+    let _result = _db.collection("users").update_one(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [replace_password_hash] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(_result.modified_count > 0)
+}
+
+
+pub async fn set_user_verified(_id: &str, _db: Database) -> Result<()> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::UserNotFound)?;
+    let filter = doc! { "_id": oid };
+    let updates = doc! { "$set": {
+        "verified_at": Utc::now(),
+        "updated_at": Utc::now()}
+        };
+    let _cursor = _db.collection("users").update_one(filter, updates, None).await.map_err(|_e| { 
+        println!("ERROR [set_user_verified] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+pub async fn set_user_oidc_subject(_id: &str, subject: &str, _db: Database) -> Result<()> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::UserNotFound)?;
+    let filter = doc! { "_id": oid };
+    let updates = doc! { "$set": {
+        "oidc_subject": subject,
+        "updated_at": Utc::now()}
+        };
+    let _cursor = _db.collection("users").update_one(filter, updates, None).await.map_err(|_e| {
+        println!("ERROR [set_user_oidc_subject] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    Ok(())
+}
+
+
+// Applies the given changes to the profile of a user. A new email address has to be verified again.
+pub async fn update_profile(_id: &str, name: Option<&str>, email: Option<&str>, role: Option<&Role>, preferences: Option<Document>, _db: Database) -> Result<()> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::UserNotFound)?;
+    let mut set = doc! { "updated_at": Utc::now() };
+    let mut updates = Document::new();
+    if let Some(name) = name {
+        set.insert("name", name);
+    }
+    let mut unset = Document::new();
+    if let Some(email) = email {
+        set.insert("email", normalize_email(email));
+        unset.insert("verified_at", "");
+    }
+    if let Some(role) = role {
+        set.insert("role", role.to_string());
+        unset.insert("leaving_admin_at", "");
+    }
+    if !unset.is_empty() {
+        updates.insert("$unset", unset);
+    }
+    if let Some(preferences) = preferences {
+        set.insert("preferences", preferences);
+    }
+    updates.insert("$set", set);
+    let _result = _db.collection("users").update_one(doc! { "_id": oid }, updates, None).await.map_err(|_e| {
+        if is_duplicate_key(&_e) {
+            return AppError::EmailExistsError;
+        }
+        println!("ERROR [update_profile] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.matched_count == 0 {
+        return Err(AppError::UserNotFound);
+    }
+    Ok(())
+}
+
+
+pub async fn delete_user(_id: &str, _db: Database) -> Result<()> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::UserNotFound)?;
+    let _result = _db.collection("users").delete_one(doc! { "_id": oid }, None).await.map_err(|_e| {
+        println!("ERROR [delete_user] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.deleted_count == 0 {
+        return Err(AppError::UserNotFound);
+    }
+    Ok(())
+}
+
+
+pub async fn set_user_disabled(_id: &str, disabled: bool, _db: Database) -> Result<()> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::UserNotFound)?;
+    let updates = if disabled {
+        doc! { "$set": { "disabled_at": Utc::now(), "updated_at": Utc::now() }, "$unset": { "leaving_admin_at": "" } }
+    } else {
+        doc! { "$set": { "updated_at": Utc::now() }, "$unset": { "disabled_at": "", "leaving_admin_at": "" } }
+    };
+    let _result = _db.collection("users").update_one(doc! { "_id": oid }, updates, None).await.map_err(|_e| {
+        println!("ERROR [set_user_disabled] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.matched_count == 0 {
+        return Err(AppError::UserNotFound);
+    }
+    Ok(())
+}
+
+
+// Admins that can still sign in and are not being removed. A removal that failed after marking the admin only keeps
+// them out of the count for LEAVING_ADMIN_TIMEOUT seconds.
+pub async fn count_active_admins(_db: Database) -> Result<i64> {
+    let leaving_since = Utc::now() - chrono::Duration::seconds(LEAVING_ADMIN_TIMEOUT);
+    let filter = doc! {
+        "role": Role::Admin.to_string(),
+        "disabled_at": null,
+        "$or": [{ "leaving_admin_at": { "$exists": false } }, { "leaving_admin_at": { "$lt": leaving_since } }],
+    };
+    _db.collection("users").count_documents(filter, None).await.map_err(|_e| {
+        println!("ERROR [count_active_admins] {:?}", _e);
+        return AppError::DataError;
+    })
+}
+
+// Refuses to delete, disable or demote the only admin that can still sign in, which would leave nobody to manage users.
+// The admin is first marked as leaving, which takes them out of the count, and only then are the others counted: of two
+// concurrent removals, at least the second sees the mark of the first. The mark is cleared by the write that changes
+// the role or disables the user, and goes along with a deleted user.
+pub async fn ensure_not_last_admin(user: &User, _db: Database) -> Result<()> {
+    if user.role != Some(Role::Admin) || user.disabled_at.is_some() {
+        return Ok(());
+    }
+    let oid = mongodb::bson::oid::ObjectId::with_string(&user.id.clone().unwrap_or_default()).map_err(|_e| AppError::UserNotFound)?;
+    let filter = doc! { "_id": oid };
+    _db.collection("users").update_one(filter.clone(), doc! { "$set": { "leaving_admin_at": Utc::now() } }, None).await.map_err(|_e| {
+        println!("ERROR [ensure_not_last_admin] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if count_active_admins(_db.clone()).await? == 0 {
+        println!("[ensure_not_last_admin] Refused to remove the last admin {}", &user.email);
+        _db.collection("users").update_one(filter, doc! { "$unset": { "leaving_admin_at": "" } }, None).await.map_err(|_e| {
+            println!("ERROR [ensure_not_last_admin] {:?}", _e);
+            return AppError::DataError;
+        })?;
+        return Err(AppError::LastAdminError);
+    }
+    Ok(())
+}
+
+// Checks that the user may be given the role: not by themselves, only to a role that exists and is within the
+// permissions of the actor, and not if it demotes the last admin. Imports from the command line have no actor.
+pub async fn ensure_role_change_allowed(_env: &Environment, actor: Option<&AuthUser>, user: &User, role: &Role) -> Result<()> {
+    if *role == user.role.clone().unwrap_or(Role::User) {
+        return Ok(());
+    }
+    if let Some(actor) = actor {
+        if user.id.as_deref() == Some(actor.id.as_str()) {
+            println!("[ensure_role_change_allowed] User {} may not change their own role", actor);
+            return Err(AppError::NoPermissionError);
+        }
+    }
+    roles::service::get_role(_env, &role.to_string()).await?;
+    if let Some(actor) = actor {
+        roles::service::ensure_role_within(_env, actor, role).await?;
+    }
+    if *role != Role::Admin {
+        ensure_not_last_admin(user, _env.db()).await?;
+    }
+    Ok(())
+}
+
+// Records when the user last uploaded an avatar, or that they have none
+pub async fn set_avatar_updated_at(_id: &str, updated_at: Option<DateTime<Utc>>, _db: Database) -> Result<()> {
+    let oid = mongodb::bson::oid::ObjectId::with_string(_id).map_err(|_e| AppError::UserNotFound)?;
+    let updates = match updated_at {
+        Some(updated_at) => doc! { "$set": { "avatar_updated_at": updated_at, "updated_at": Utc::now() } },
+        None => doc! { "$unset": { "avatar_updated_at": "" }, "$set": { "updated_at": Utc::now() } },
+    };
+    let _result = _db.collection("users").update_one(doc! { "_id": oid }, updates, None).await.map_err(|_e| {
+        println!("ERROR [set_avatar_updated_at] {:?}", _e);
+        return AppError::DataError;
+    })?;
+    if _result.matched_count == 0 {
+        return Err(AppError::UserNotFound);
+    }
+    Ok(())
+}
+
+
+// Deletes the account of a user along with their API keys, two-factor settings and avatar, and signs out all of their sessions.
+// Their comments are kept, without their name and email address. The user document goes last, so that a deletion
+// that failed halfway can be run again.
+pub async fn delete_account(_env: &Environment, user: &User) -> Result<()> {
+    let user_id = user.id.clone().unwrap_or_default();
+    ensure_not_last_admin(user, _env.db()).await?;
+    articles::service::anonymize_comments(&user.email, _env.db()).await?;
+    api_keys::service::delete_user_api_keys(&user_id, _env.db()).await?;
+    auth::service::disable_mfa(&user_id, _env.db()).await?;
+    avatars::service::delete_avatar_files(_env, &user_id).await?;
+    sign_out_everywhere(_env, &user_id).await?;
+    delete_user(&user_id, _env.db()).await
+}
+
+// Revokes the tokens already issued to the user and ends all of their sessions
+pub async fn sign_out_everywhere(_env: &Environment, user_id: &str) -> Result<()> {
+    auth::revocation::revoke_user_tokens(_env, user_id).await?;
+    auth::service::revoke_user_refresh_tokens(user_id, _env.db()).await?;
+    sessions::service::delete_user_sessions(user_id, _env.db()).await
+}
+
+// Like sign_out_everywhere, but the session `session_id` stays signed in. Its access tokens are revoked along with the
+// others, and its refresh token gets new ones.
+pub async fn sign_out_other_sessions(_env: &Environment, user_id: &str, session_id: &str) -> Result<()> {
+    auth::revocation::revoke_user_tokens(_env, user_id).await?;
+    auth::service::revoke_other_refresh_tokens(user_id, session_id, _env.db()).await?;
+    sessions::service::delete_other_sessions(user_id, session_id, _env.db()).await
+}
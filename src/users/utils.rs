@@ -1,11 +1,17 @@
-use mongodb::bson::{doc};
+use std::convert::TryFrom;
+
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::Collation;
+use serde_json::{Map, Value};
 use tokio::stream::StreamExt;
-use chrono::Utc;
+use chrono::{TimeZone, Utc};
 
 use crate::Result;
 use crate::auth::models::{Role};
-use crate::users::models::{User};
-use crate::error::{AppError};
+use crate::users::{DUPLICATE_KEY_CODE, PREFERENCES_MAX_KEYS, PREFERENCE_KEY_MAX_LENGTH, PREFERENCE_VALUE_MAX_LENGTH};
+use crate::users::models::{User, UserCursor, UserQuery};
+use crate::error::{AppError, FieldError};
 
 
 pub async fn parse_users(mut _cursor: mongodb::Cursor) -> Result<Vec<User>> {
@@ -32,28 +38,62 @@ pub fn doc_to_user(doc: &mongodb::bson::document::Document) -> Result<User> {
     let name = doc.get_str("name")?;
     let email = doc.get_str("email")?;
     let role = doc.get_str("role")?;
-    let password = doc.get_str("password")?;
+    let password = doc.get_str("password").ok();
     let created_at = doc.get_datetime("created_at")?;
     let updated_at = doc.get_datetime("updated_at")?;
+    let verified_at = doc.get_datetime("verified_at").ok();
+    let disabled_at = doc.get_datetime("disabled_at").ok();
+    // The time of the upload keeps clients from showing a cached previous avatar
+    let avatar_url = doc.get_datetime("avatar_updated_at").ok()
+        .map(|updated_at| format!("/api/users/{}/avatar?v={}", id, updated_at.timestamp()));
+    let preferences = match doc.get_document("preferences").map(|d| Bson::Document(d.clone()).into_relaxed_extjson()) {
+        Ok(Value::Object(preferences)) => Some(preferences),
+        _ => None,
+    };
 
     let result = User {
         id: Some(id.to_string()),
         name: name.to_owned(),
         email: email.to_owned(),
         role: Some(Role::from_str(&role.to_owned())),
-        password: Some(password.to_owned()),
+        password: password.map(str::to_owned),
         created_at: Some(*created_at),
         updated_at: Some(*updated_at),
+        verified_at: verified_at.copied(),
+        disabled_at: disabled_at.copied(),
+        preferences,
+        avatar_url,
     };
     Ok(result)
 }
 
 
+// Email addresses are stored trimmed and in lowercase, and compared regardless of case
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+
+// Collation of the unique index on `users.email`, which queries by email use to match regardless of case
+pub fn email_collation() -> Collation {
+    Collation::builder().locale("en".to_owned()).strength(2).build()
+}
+
+
+// Whether a write was refused by a unique index, such as the one on `users.email`
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::WriteError(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY_CODE,
+        ErrorKind::CommandError(e) => e.code == DUPLICATE_KEY_CODE,
+        _ => false,
+    }
+}
+
+
 pub fn user_to_doc(_user: &User) -> mongodb::bson::document::Document {
-    let doc = doc! {
-    "email": _user.email.clone(),
+    let mut doc = doc! {
+    "email": normalize_email(&_user.email),
     "name": _user.name.clone(),
-    "password": _user.password.clone().unwrap(),
     "role": _user.role.clone().unwrap().to_string(),
     "created_at": _user.created_at.clone().unwrap(),
     "updated_at": Utc::now()
@@ -63,5 +103,221 @@ pub fn user_to_doc(_user: &User) -> mongodb::bson::document::Document {
     //     Some(v) => doc.insert("updated_at", v),
     //     None => None
     // };
+    // Users signing in through single sign-on only may have no password
+    if let Some(password) = &_user.password {
+        doc.insert("password", password);
+    }
+    if let Some(verified_at) = _user.verified_at {
+        doc.insert("verified_at", verified_at);
+    }
     return doc;
 }
+
+
+// Preferences are a flat object of short keys and scalar values
+pub fn validate_preferences(preferences: &Map<String, Value>) -> Result<()> {
+    let mut errors = Vec::new();
+    if preferences.len() > PREFERENCES_MAX_KEYS {
+        errors.push(FieldError::new("preferences", "too_many", &format!("must have at most {} keys", PREFERENCES_MAX_KEYS)));
+    }
+    for (key, value) in preferences {
+        let valid_key = !key.is_empty() && key.len() <= PREFERENCE_KEY_MAX_LENGTH
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        let valid_value = match value {
+            Value::String(value) => value.len() <= PREFERENCE_VALUE_MAX_LENGTH,
+            Value::Bool(_) | Value::Number(_) => true,
+            _ => false,
+        };
+        if !valid_key || !valid_value {
+            let message = format!("{:?} must be a key of letters, digits, '_' or '-' with a string, number or boolean value", key);
+            errors.push(FieldError::new("preferences", "invalid", &message));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AppError::ValidationError(errors));
+    }
+    Ok(())
+}
+
+
+// Validated preferences have no nested objects or `$` keys, so they cannot be taken for extended JSON
+pub fn preferences_to_doc(preferences: &Map<String, Value>) -> Result<Document> {
+    Document::try_from(preferences.clone()).map_err(|_e| {
+        println!("ERROR [preferences_to_doc] {:?}", _e);
+        return AppError::DataError;
+    })
+}
+
+
+// Field and direction of a user listing sort such as `-created_at`
+pub fn parse_sort(sort: &str) -> Result<(&'static str, bool)> {
+    let (name, descending) = match sort.strip_prefix('-') {
+        Some(name) => (name, true),
+        None => (sort, false),
+    };
+    let field = match name {
+        "created_at" => "created_at",
+        "email" => "email",
+        "name" => "name",
+        _ => return Err(AppError::ValidationError(vec![
+            FieldError::new("sort", "invalid", "must be created_at, email or name, optionally prefixed with '-'")])),
+    };
+    Ok((field, descending))
+}
+
+
+pub fn user_filter(query: &UserQuery) -> Document {
+    let mut filter = Document::new();
+    if let Some(role) = query.role.as_deref().filter(|role| !role.is_empty()) {
+        filter.insert("role", role);
+    }
+    if let Some(email) = query.email.as_deref().filter(|email| !email.is_empty()) {
+        filter.insert("email", prefix_regex(email));
+    }
+    if let Some(name) = query.name.as_deref().filter(|name| !name.is_empty()) {
+        filter.insert("name", prefix_regex(name));
+    }
+    let mut created_at = Document::new();
+    if let Some(created_after) = query.created_after {
+        created_at.insert("$gte", created_after);
+    }
+    if let Some(created_before) = query.created_before {
+        created_at.insert("$lt", created_before);
+    }
+    if !created_at.is_empty() {
+        filter.insert("created_at", created_at);
+    }
+    match query.disabled {
+        Some(true) => filter.insert("disabled_at", doc! { "$ne": Bson::Null }),
+        Some(false) => filter.insert("disabled_at", Bson::Null),
+        None => None,
+    };
+    filter
+}
+
+
+// Case-insensitive match of the start of a field, with the prefix taken literally
+fn prefix_regex(prefix: &str) -> Document {
+    let escaped: String = prefix.chars()
+        .map(|c| if "\\^$.|?*+()[]{}".contains(c) { format!("\\{}", c) } else { c.to_string() })
+        .collect();
+    doc! { "$regex": format!("^{}", escaped), "$options": "i" }
+}
+
+
+pub fn encode_cursor(sort: &str, field: &str, user: &User) -> String {
+    let value = match field {
+        "created_at" => user.created_at.map(|created_at| created_at.timestamp_millis()).unwrap_or_default().to_string(),
+        "email" => user.email.clone(),
+        _ => user.name.clone(),
+    };
+    let cursor = UserCursor { sort: sort.to_owned(), value, id: user.id.clone().unwrap_or_default() };
+    base64::encode_config(serde_json::to_string(&cursor).unwrap_or_default(), base64::URL_SAFE_NO_PAD)
+}
+
+
+// Users after the cursor in the sort order. Ties on the sort field are broken by id.
+pub fn cursor_filter(cursor: &str, sort: &str, field: &str, descending: bool) -> Result<Document> {
+    let invalid = || AppError::ValidationError(vec![FieldError::new("cursor", "invalid", "must be the next_cursor of a page with the same sort")]);
+    let cursor: UserCursor = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(invalid)?;
+    if cursor.sort != sort {
+        return Err(invalid());
+    }
+    let id = ObjectId::with_string(&cursor.id).map_err(|_e| invalid())?;
+    let value = match field {
+        "created_at" => {
+            let millis = cursor.value.parse::<i64>().map_err(|_e| invalid())?;
+            Bson::from(Utc.timestamp_millis_opt(millis).single().ok_or_else(invalid)?)
+        },
+        _ => Bson::from(cursor.value),
+    };
+    let operator = if descending { "$lt" } else { "$gt" };
+    let mut after_value = Document::new();
+    after_value.insert(field, doc! { operator: value.clone() });
+    let mut same_value = Document::new();
+    same_value.insert(field, value);
+    same_value.insert("_id", doc! { operator: id });
+    Ok(doc! { "$or": [after_value, same_value] })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "5f1b2c3d4e5f6a7b8c9d0e1f";
+
+    fn user() -> User {
+        User {
+            id: Some(ID.to_owned()),
+            name: "Jane".to_owned(),
+            email: "jane@example.com".to_owned(),
+            role: Some(Role::User),
+            password: None,
+            created_at: Some(Utc.timestamp_millis_opt(1600000000123).unwrap()),
+            updated_at: None,
+            verified_at: None,
+            disabled_at: None,
+            preferences: None,
+            avatar_url: None,
+        }
+    }
+
+    fn is_invalid(result: Result<Document>, field: &str) -> bool {
+        match result {
+            Err(AppError::ValidationError(errors)) => errors.iter().all(|e| e.field == field && e.code == "invalid"),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn parses_sorts() {
+        assert!(matches!(parse_sort("created_at"), Ok(("created_at", false))));
+        assert!(matches!(parse_sort("-email"), Ok(("email", true))));
+        assert!(matches!(parse_sort("name"), Ok(("name", false))));
+        assert!(matches!(parse_sort("password"), Err(AppError::ValidationError(_))));
+        assert!(matches!(parse_sort("--name"), Err(AppError::ValidationError(_))));
+        assert!(matches!(parse_sort(""), Err(AppError::ValidationError(_))));
+    }
+
+    #[test]
+    fn round_trips_created_at_cursors() {
+        let cursor = encode_cursor("-created_at", "created_at", &user());
+        let filter = cursor_filter(&cursor, "-created_at", "created_at", true).unwrap();
+        let created_at = Utc.timestamp_millis_opt(1600000000123).unwrap();
+        let id = ObjectId::with_string(ID).unwrap();
+        assert_eq!(filter, doc! { "$or": [
+            { "created_at": { "$lt": created_at } },
+            { "created_at": created_at, "_id": { "$lt": id } },
+        ] });
+    }
+
+    #[test]
+    fn round_trips_text_cursors() {
+        let cursor = encode_cursor("email", "email", &user());
+        let filter = cursor_filter(&cursor, "email", "email", false).unwrap();
+        let id = ObjectId::with_string(ID).unwrap();
+        assert_eq!(filter, doc! { "$or": [
+            { "email": { "$gt": "jane@example.com" } },
+            { "email": "jane@example.com", "_id": { "$gt": id } },
+        ] });
+    }
+
+    #[test]
+    fn refuses_cursors_of_another_sort() {
+        let cursor = encode_cursor("name", "name", &user());
+        assert!(is_invalid(cursor_filter(&cursor, "-name", "name", true), "cursor"));
+    }
+
+    #[test]
+    fn refuses_malformed_cursors() {
+        assert!(is_invalid(cursor_filter("not a cursor", "name", "name", false), "cursor"));
+        let encode = |json: &str| base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+        let bad_id = encode(r#"{"sort":"name","value":"Jane","id":"nope"}"#);
+        assert!(is_invalid(cursor_filter(&bad_id, "name", "name", false), "cursor"));
+        let bad_time = encode(&format!(r#"{{"sort":"created_at","value":"yesterday","id":"{}"}}"#, ID));
+        assert!(is_invalid(cursor_filter(&bad_time, "created_at", "created_at", false), "cursor"));
+    }
+}